@@ -1,72 +1,769 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufRead, Write};
 use std::str::FromStr;
 
-use regex::Regex;
+use crate::grid::Grid;
 
-#[derive(Debug, PartialEq)]
-enum Instruction {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Register {
+    X,
+    A,
+    B,
+    C,
+}
+
+impl Register {
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x" => Ok(Register::X),
+            "a" => Ok(Register::A),
+            "b" => Ok(Register::B),
+            "c" => Ok(Register::C),
+            _ => Err(format!("Unknown register {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Register::X => "x",
+            Register::A => "a",
+            Register::B => "b",
+            Register::C => "c",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Reg(Register),
+    Imm(isize),
+}
+
+impl FromStr for Operand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(value) => Ok(Operand::Imm(value)),
+            Err(_) => s.parse().map(Operand::Reg),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Reg(reg) => write!(f, "{}", reg),
+            Operand::Imm(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Jumps are relative to the jumping instruction, so `jmp -1` loops on the previous line.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instruction {
     NOOP,
     ADDX(isize),
+    MOV(Register, Operand),
+    ADD(Register, Operand),
+    SUB(Register, Operand),
+    MUL(Register, Operand),
+    JMP(isize),
+    JZ(Register, isize),
+    JNZ(Register, isize),
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    NOOP,
+    ADDX,
+    MOV,
+    ADD,
+    SUB,
+    MUL,
+    JMP,
+    JZ,
+    JNZ,
+}
+
+impl Instruction {
+    fn opcode(&self) -> Opcode {
+        match self {
+            Instruction::NOOP => Opcode::NOOP,
+            Instruction::ADDX(_) => Opcode::ADDX,
+            Instruction::MOV(_, _) => Opcode::MOV,
+            Instruction::ADD(_, _) => Opcode::ADD,
+            Instruction::SUB(_, _) => Opcode::SUB,
+            Instruction::MUL(_, _) => Opcode::MUL,
+            Instruction::JMP(_) => Opcode::JMP,
+            Instruction::JZ(_, _) => Opcode::JZ,
+            Instruction::JNZ(_, _) => Opcode::JNZ,
+        }
+    }
 }
 
 impl FromStr for Instruction {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let matcher = Regex::new("^(?<noop>noop)|((?<addx>addx) (?<num>-?\\d+))$")
-            .expect("Could not build regex");
-        let Some(cap) = matcher.captures(s) else {
-            println!("No match");
-            return Err("No match".to_owned());
+        let tokens: Vec<&str> = s.split_whitespace().collect();
+        let num = |token: &str| -> Result<isize, String> {
+            token.parse().map_err(|_| format!("Expected a number, got {}", token))
         };
-        if cap.name("noop").is_some() {
-            return Ok(Instruction::NOOP);
-        } else if cap.name("addx").is_some() {
-            return Ok(Instruction::ADDX(
-                cap.name("num")
-                    .unwrap()
-                    .as_str()
-                    .parse()
-                    .expect("Parsing error"),
-            ));
+        match tokens.as_slice() {
+            ["noop"] => Ok(Instruction::NOOP),
+            ["addx", n] => Ok(Instruction::ADDX(num(n)?)),
+            ["mov", reg, op] => Ok(Instruction::MOV(reg.parse()?, op.parse()?)),
+            ["add", reg, op] => Ok(Instruction::ADD(reg.parse()?, op.parse()?)),
+            ["sub", reg, op] => Ok(Instruction::SUB(reg.parse()?, op.parse()?)),
+            ["mul", reg, op] => Ok(Instruction::MUL(reg.parse()?, op.parse()?)),
+            ["jmp", offset] => Ok(Instruction::JMP(num(offset)?)),
+            ["jz", reg, offset] => Ok(Instruction::JZ(reg.parse()?, num(offset)?)),
+            ["jnz", reg, offset] => Ok(Instruction::JNZ(reg.parse()?, num(offset)?)),
+            _ => Err(format!("Could not parse {}", s)),
+        }
+    }
+}
+
+/// Canonical assembly text, which parses back to the same instruction.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::NOOP => write!(f, "noop"),
+            Instruction::ADDX(x) => write!(f, "addx {}", x),
+            Instruction::MOV(reg, op) => write!(f, "mov {} {}", reg, op),
+            Instruction::ADD(reg, op) => write!(f, "add {} {}", reg, op),
+            Instruction::SUB(reg, op) => write!(f, "sub {} {}", reg, op),
+            Instruction::MUL(reg, op) => write!(f, "mul {} {}", reg, op),
+            Instruction::JMP(offset) => write!(f, "jmp {}", offset),
+            Instruction::JZ(reg, offset) => write!(f, "jz {} {}", reg, offset),
+            Instruction::JNZ(reg, offset) => write!(f, "jnz {} {}", reg, offset),
         }
+    }
+}
+
+/// Number of cycles each opcode takes. The puzzle's `noop` and `addx` keep their 1 and 2.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CycleCosts(HashMap<Opcode, usize>);
+
+impl CycleCosts {
+    pub fn with(mut self, opcode: Opcode, cycles: usize) -> Self {
+        assert!(cycles > 0, "An instruction takes at least one cycle");
+        self.0.insert(opcode, cycles);
+        self
+    }
+
+    pub fn get(&self, opcode: Opcode) -> usize {
+        self.0[&opcode]
+    }
+}
 
-        Err("Could not parse".to_owned())
+impl Default for CycleCosts {
+    fn default() -> Self {
+        CycleCosts(HashMap::from([
+            (Opcode::NOOP, 1),
+            (Opcode::ADDX, 2),
+            (Opcode::MOV, 1),
+            (Opcode::ADD, 1),
+            (Opcode::SUB, 1),
+            (Opcode::MUL, 2),
+            (Opcode::JMP, 1),
+            (Opcode::JZ, 1),
+            (Opcode::JNZ, 1),
+        ]))
     }
 }
 
-struct CPU {
-    register_x: isize,
+#[allow(clippy::upper_case_acronyms)]
+pub struct CPU {
+    registers: [isize; 4],
     cycle: usize,
+    pc: usize,
+    costs: CycleCosts,
 }
 
 impl CPU {
-    fn new() -> Self {
+    pub fn new() -> Self {
+        CPU::with_costs(CycleCosts::default())
+    }
+
+    pub fn with_costs(costs: CycleCosts) -> Self {
         CPU {
-            register_x: 1,
+            registers: [1, 0, 0, 0],
             cycle: 0,
+            pc: 0,
+            costs,
         }
     }
 
-    fn do_instr(&mut self, instr: &Instruction) -> HashMap<usize, isize> {
-        match instr {
-            Instruction::NOOP => {
-                self.cycle += 1;
-                HashMap::from([(self.cycle, self.register_x)])
-            }
+    pub fn register(&self, reg: Register) -> isize {
+        self.registers[reg.index()]
+    }
+
+    /// Number of completed cycles.
+    pub fn cycle(&self) -> usize {
+        self.cycle
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    fn value(&self, operand: Operand) -> isize {
+        match operand {
+            Operand::Reg(reg) => self.register(reg),
+            Operand::Imm(value) => value,
+        }
+    }
+
+    /// The instruction at the program counter, or `None` once it has left the program.
+    fn fetch<'a>(&self, instructions: &'a [Instruction]) -> Option<&'a Instruction> {
+        instructions.get(self.pc)
+    }
+
+    pub fn cycles<'a>(&'a mut self, instructions: &'a [Instruction]) -> Cycles<'a> {
+        Cycles {
+            cpu: self,
+            instructions,
+            in_flight: None,
+        }
+    }
+
+    fn execute(&mut self, instr: &Instruction) {
+        // a jump past either end of the address space leaves the program like any other
+        let pc = self.pc as isize;
+        let jump = |offset: isize| pc.checked_add(offset);
+        let mut next_pc = jump(1);
+        match *instr {
+            Instruction::NOOP => {}
+            // registers wrap like machine words, so a runaway loop can't panic in debug builds
             Instruction::ADDX(x) => {
-                self.cycle += 2;
-                self.register_x += x;
-                HashMap::from([
-                    (self.cycle - 2, self.register_x - x),
-                    (self.cycle - 1, self.register_x - x),
-                    (self.cycle, self.register_x),
-                ])
+                self.registers[Register::X.index()] = self.register(Register::X).wrapping_add(x)
+            },
+            Instruction::MOV(reg, op) => self.registers[reg.index()] = self.value(op),
+            Instruction::ADD(reg, op) => {
+                self.registers[reg.index()] = self.register(reg).wrapping_add(self.value(op))
+            },
+            Instruction::SUB(reg, op) => {
+                self.registers[reg.index()] = self.register(reg).wrapping_sub(self.value(op))
+            },
+            Instruction::MUL(reg, op) => {
+                self.registers[reg.index()] = self.register(reg).wrapping_mul(self.value(op))
+            },
+            Instruction::JMP(offset) => next_pc = jump(offset),
+            Instruction::JZ(reg, offset) if self.register(reg) == 0 => next_pc = jump(offset),
+            Instruction::JNZ(reg, offset) if self.register(reg) != 0 => next_pc = jump(offset),
+            Instruction::JZ(_, _) | Instruction::JNZ(_, _) => {}
+        }
+        self.pc = next_pc.and_then(|pc| usize::try_from(pc).ok()).unwrap_or(usize::MAX);
+    }
+}
+
+/// X during and after one clock cycle, counting cycles from 1 like the puzzle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CycleState {
+    pub cycle: usize,
+    pub x_during: isize,
+    pub x_after: isize,
+}
+
+/// Steps the CPU one cycle per `next`. Registers only change at the end of an instruction's last
+/// cycle, so every cycle it spans still sees the old X. Ends once the program counter leaves the
+/// program, so a program that loops forever yields forever.
+pub struct Cycles<'a> {
+    cpu: &'a mut CPU,
+    instructions: &'a [Instruction],
+    // instruction being executed and the cycles it still needs
+    in_flight: Option<(&'a Instruction, usize)>,
+}
+
+impl Iterator for Cycles<'_> {
+    type Item = CycleState;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (instr, remaining) = match self.in_flight {
+            Some(in_flight) => in_flight,
+            None => {
+                let instr = self.cpu.fetch(self.instructions)?;
+                (instr, self.cpu.costs.get(instr.opcode()))
             }
+        };
+        let x_during = self.cpu.register(Register::X);
+        self.cpu.cycle += 1;
+        if remaining == 1 {
+            self.cpu.execute(instr);
+            self.in_flight = None;
+        } else {
+            self.in_flight = Some((instr, remaining - 1));
+        }
+
+        Some(CycleState {
+            cycle: self.cpu.cycle,
+            x_during,
+            x_after: self.cpu.register(Register::X),
+        })
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stops once the given cycle has completed.
+    Cycle(usize),
+    /// Stops just before the instruction at this index starts.
+    Instruction(usize),
+    /// Stops when a register changes to the value.
+    Register(Register, isize),
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens: Vec<&str> = s.split_whitespace().collect();
+        let num = |token: &str| token.parse().map_err(|_| format!("Expected a number, got {}", token));
+        match tokens.as_slice() {
+            ["cycle", n] => Ok(Breakpoint::Cycle(num(n)?)),
+            ["instr", n] => Ok(Breakpoint::Instruction(num(n)?)),
+            ["reg", reg, value] => Ok(Breakpoint::Register(
+                reg.parse()?,
+                value.parse().map_err(|_| format!("Expected a number, got {}", value))?,
+            )),
+            _ => Err(format!("Unknown breakpoint {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Cycle(cycle) => write!(f, "cycle {}", cycle),
+            Breakpoint::Instruction(idx) => write!(f, "instr {}", idx),
+            Breakpoint::Register(reg, value) => write!(f, "reg {} {}", reg, value),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(Breakpoint, CycleState),
+    Finished,
+}
+
+/// Runs a program cycle by cycle under breakpoints. Commands are one per line:
+///
+/// * `break <bp>` / `delete <bp>` where `<bp>` is `cycle N`, `instr N` or `reg R V`
+/// * `step [N]` runs N cycles (default 1), `continue` runs to the next breakpoint
+/// * `print <reg|cycle|pc>` and `regs` inspect the CPU
+///
+/// Blank lines and lines starting with `#` are ignored.
+pub struct Debugger<'a> {
+    cycles: Cycles<'a>,
+    breakpoints: Vec<Breakpoint>,
+    // whether anything has run yet, i.e. whether breakpoints before the first cycle were checked
+    started: bool,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(cpu: &'a mut CPU, instructions: &'a [Instruction]) -> Self {
+        Debugger {
+            cycles: cpu.cycles(instructions),
+            breakpoints: Vec::new(),
+            started: false,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|&bp| bp != breakpoint);
+        self.breakpoints.len() != before
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        self.cycles.cpu
+    }
+
+    pub fn step(&mut self) -> Option<CycleState> {
+        self.started = true;
+        self.cycles.next()
+    }
+
+    fn hit(&self, state: &CycleState, registers_before: &[isize; 4]) -> Option<Breakpoint> {
+        let cpu = self.cpu();
+        self.breakpoints.iter().copied().find(|bp| match *bp {
+            Breakpoint::Cycle(cycle) => state.cycle == cycle,
+            Breakpoint::Instruction(idx) => {
+                self.cycles.in_flight.is_none() && cpu.pc == idx && idx < self.cycles.instructions.len()
+            }
+            Breakpoint::Register(reg, value) => {
+                cpu.register(reg) == value && registers_before[reg.index()] != value
+            }
+        })
+    }
+
+    /// Runs until the first breakpoint hit or the end. Only the very first call can stop before
+    /// running a cycle, for a breakpoint on the instruction the program starts at; after that it
+    /// always runs at least one cycle, so resuming from a stop makes progress.
+    pub fn resume(&mut self) -> Stop {
+        if !self.started {
+            self.started = true;
+            let cpu = self.cpu();
+            let x = cpu.register(Register::X);
+            let state = CycleState { cycle: cpu.cycle, x_during: x, x_after: x };
+            if let Some(bp) = self.hit(&state, &cpu.registers) {
+                return Stop::Breakpoint(bp, state);
+            }
+        }
+        loop {
+            let registers_before = self.cpu().registers;
+            let Some(state) = self.step() else {
+                return Stop::Finished;
+            };
+            if let Some(bp) = self.hit(&state, &registers_before) {
+                return Stop::Breakpoint(bp, state);
+            }
+        }
+    }
+
+    pub fn execute_command(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "" => Ok(String::new()),
+            _ if command.starts_with('#') => Ok(String::new()),
+            "break" => {
+                self.add_breakpoint(args.parse()?);
+                Ok(format!("breakpoint {}", args))
+            }
+            "delete" => match self.remove_breakpoint(args.parse()?) {
+                true => Ok(format!("deleted {}", args)),
+                false => Err(format!("No breakpoint {}", args)),
+            },
+            "step" => {
+                let count: usize = if args.is_empty() {
+                    1
+                } else {
+                    args.parse().map_err(|_| format!("Expected a number, got {}", args))?
+                };
+                let mut out = Vec::new();
+                for _ in 0..count {
+                    match self.step() {
+                        Some(state) => out.push(describe(&state)),
+                        None => {
+                            out.push(format!("finished at cycle {}", self.cpu().cycle));
+                            break;
+                        }
+                    }
+                }
+                Ok(out.join("\n"))
+            }
+            "continue" => match self.resume() {
+                Stop::Breakpoint(bp, state) => Ok(format!("hit {}: {}", bp, describe(&state))),
+                Stop::Finished => Ok(format!("finished at cycle {}", self.cpu().cycle)),
+            },
+            "print" => match args {
+                "cycle" => Ok(format!("cycle = {}", self.cpu().cycle)),
+                "pc" => Ok(format!("pc = {}", self.cpu().pc)),
+                reg => Ok(format!("{} = {}", reg, self.cpu().register(reg.parse()?))),
+            },
+            "regs" => {
+                let cpu = self.cpu();
+                Ok(format!(
+                    "x = {}, a = {}, b = {}, c = {}, cycle = {}, pc = {}",
+                    cpu.register(Register::X),
+                    cpu.register(Register::A),
+                    cpu.register(Register::B),
+                    cpu.register(Register::C),
+                    cpu.cycle,
+                    cpu.pc
+                ))
+            }
+            _ => Err(format!("Unknown command {}", command)),
+        }
+    }
+
+    /// Runs a command file, returning everything the commands printed or the first error with its
+    /// line number.
+    pub fn run_script(&mut self, script: &str) -> Result<String, String> {
+        let mut out = String::new();
+        for (line_no, line) in script.lines().enumerate() {
+            let output = self.execute_command(line).map_err(|err| format!("line {}: {}", line_no + 1, err))?;
+            if !output.is_empty() {
+                out.push_str(&output);
+                out.push('\n');
+            }
+        }
+        Ok(out)
+    }
+
+    /// Reads commands interactively, reporting errors and carrying on until the input ends.
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        for line in input.lines() {
+            match self.execute_command(&line?) {
+                Ok(out) if out.is_empty() => {}
+                Ok(out) => writeln!(output, "{}", out)?,
+                Err(err) => writeln!(output, "error: {}", err)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn describe(state: &CycleState) -> String {
+    format!("cycle {}: x = {} -> {}", state.cycle, state.x_during, state.x_after)
+}
+
+/// Writes the run as a Value Change Dump so it can be opened in a waveform viewer. Each cycle is
+/// one time unit, from `#0` for the first cycle to the final cycle count, where only X's last
+/// value is dumped. The beam column wraps at `crt`'s width. Returns the number of cycles run.
+pub fn trace_cpu<W: Write>(cpu: &mut CPU, instructions: &[Instruction], crt: &Crt, mut out: W) -> io::Result<usize> {
+    const SIGNALS: [(&str, &str, &str); 5] = [
+        ("integer 64", "!", "x"),
+        ("integer 32", "\"", "cycle"),
+        ("integer 32", "#", "pc"),
+        ("string 1", "$", "instruction"),
+        ("integer 32", "%", "beam_column"),
+    ];
+    writeln!(out, "$version aoc22 day10 $end")?;
+    writeln!(out, "$timescale 1ns $end")?;
+    writeln!(out, "$scope module cpu $end")?;
+    for (kind, id, name) in SIGNALS {
+        writeln!(out, "$var {} {} {} $end", kind, id, name)?;
+    }
+    writeln!(out, "$upscope $end")?;
+    writeln!(out, "$enddefinitions $end")?;
+
+    let binary = |value: isize| format!("b{:b}", value as i64);
+    let mut last: [String; 5] = Default::default();
+    // writes the values that changed under a `#time` header, returning whether anything did
+    let mut write_changes = |out: &mut W, time: usize, values: [Option<String>; 5]| -> io::Result<bool> {
+        let mut header_written = false;
+        for (idx, value) in values.into_iter().enumerate() {
+            let Some(value) = value.filter(|value| *value != last[idx]) else {
+                continue;
+            };
+            if !header_written {
+                writeln!(out, "#{}", time)?;
+                header_written = true;
+            }
+            writeln!(out, "{} {}", value, SIGNALS[idx].1)?;
+            last[idx] = value;
+        }
+        Ok(header_written)
+    };
+
+    let mut time = 0;
+    let mut cycles = cpu.cycles(instructions);
+    loop {
+        let pc = cycles.cpu.pc;
+        let instruction = instructions.get(pc).map(|instr| instr.to_string().replace(' ', "_"));
+        let Some(state) = cycles.next() else {
+            break;
+        };
+        write_changes(&mut out, state.cycle - 1, [
+            Some(binary(state.x_during)),
+            Some(binary(state.cycle as isize)),
+            Some(binary(pc as isize)),
+            instruction.map(|name| format!("s{}", name)),
+            Some(binary(((state.cycle - 1) % crt.width()) as isize)),
+        ])?;
+        time = state.cycle;
+    }
+    let x = cycles.cpu.register(Register::X);
+    if !write_changes(&mut out, time, [Some(binary(x)), None, None, None, None])? {
+        writeln!(out, "#{}", time)?;
+    }
+
+    Ok(time)
+}
+
+#[derive(Default)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Vec<String>>,
+}
+
+/// Macro expansions nested deeper than this are assumed to be recursive.
+const MAX_MACRO_DEPTH: usize = 16;
+
+/// Collects source lines into statements, expanding macros and recording constants as it goes.
+/// Labels are only resolved in `finish`, once every address is known, so jumps can go forwards.
+#[derive(Default)]
+struct Assembler {
+    constants: HashMap<String, isize>,
+    macros: HashMap<String, Macro>,
+    recording: Option<(String, Macro)>,
+    // source line and tokens, where a lone `name:` token marks a label
+    statements: Vec<(usize, Vec<String>)>,
+}
+
+impl Assembler {
+    fn feed(&mut self, line_no: usize, line: &str) -> Result<(), String> {
+        let code = line.split([';', '#']).next().unwrap_or("");
+        let tokens: Vec<String> = code.split_whitespace().map(str::to_owned).collect();
+        let Some(first) = tokens.first() else {
+            return Ok(());
+        };
+
+        if let Some((_, recording)) = &mut self.recording {
+            if first != ".endm" {
+                recording.body.push(tokens);
+                return Ok(());
+            }
+        }
+        match first.as_str() {
+            ".const" => {
+                let [_, name, value] = tokens.as_slice() else {
+                    return Err("Expected .const NAME VALUE".to_owned());
+                };
+                if name.parse::<Register>().is_ok() {
+                    return Err(format!("Constant {} shadows a register", name));
+                }
+                let value = self.constant(value).or_else(|| value.parse().ok())
+                    .ok_or(format!("Expected a number, got {}", value))?;
+                self.constants.insert(name.clone(), value);
+                Ok(())
+            }
+            ".macro" => {
+                let Some(name) = tokens.get(1) else {
+                    return Err("Expected .macro NAME PARAMS...".to_owned());
+                };
+                let params = tokens[2..].to_vec();
+                self.recording = Some((name.clone(), Macro { params, body: Vec::new() }));
+                Ok(())
+            }
+            ".endm" => match self.recording.take() {
+                Some((name, recorded)) => {
+                    self.macros.insert(name, recorded);
+                    Ok(())
+                }
+                None => Err(".endm outside a macro".to_owned()),
+            },
+            _ => self.expand(line_no, tokens, 0),
+        }
+    }
+
+    fn constant(&self, token: &str) -> Option<isize> {
+        self.constants.get(token).copied()
+    }
+
+    fn expand(&mut self, line_no: usize, mut tokens: Vec<String>, depth: usize) -> Result<(), String> {
+        if depth > MAX_MACRO_DEPTH {
+            return Err("Macros nested too deeply".to_owned());
+        }
+        while tokens.first().is_some_and(|token| token.ends_with(':')) {
+            self.statements.push((line_no, vec![tokens.remove(0)]));
+        }
+        let Some(name) = tokens.first() else {
+            return Ok(());
+        };
+        let Some(found) = self.macros.get(name) else {
+            self.statements.push((line_no, tokens));
+            return Ok(());
+        };
+
+        let args = &tokens[1..];
+        if args.len() != found.params.len() {
+            return Err(format!("Macro {} takes {} arguments, got {}", name, found.params.len(), args.len()));
+        }
+        let body: Vec<Vec<String>> = found.body.iter()
+            .map(|line| line.iter()
+                .map(|token| match found.params.iter().position(|param| param == token) {
+                    Some(idx) => args[idx].clone(),
+                    None => token.clone(),
+                })
+                .collect())
+            .collect();
+        for line in body {
+            self.expand(line_no, line, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<Instruction>, String> {
+        if let Some((name, _)) = self.recording {
+            return Err(format!("Macro {} is missing .endm", name));
+        }
+
+        // first pass: give every label the address of the instruction after it
+        let mut labels: HashMap<&str, usize> = HashMap::new();
+        let mut address = 0;
+        for (line_no, tokens) in &self.statements {
+            match tokens[0].strip_suffix(':') {
+                Some(label) => {
+                    if labels.insert(label, address).is_some() {
+                        return Err(format!("line {}: Duplicate label {}", line_no, label));
+                    }
+                }
+                None => address += 1,
+            }
+        }
+
+        // second pass: swap labels and constants for numbers and parse the result
+        let mut instructions = Vec::with_capacity(address);
+        for (line_no, tokens) in &self.statements {
+            if tokens[0].ends_with(':') {
+                continue;
+            }
+            let here = instructions.len();
+            let is_jump = matches!(tokens[0].as_str(), "jmp" | "jz" | "jnz");
+            let resolved: Vec<String> = tokens.iter().enumerate()
+                .map(|(idx, token)| match (labels.get(token.as_str()), self.constant(token)) {
+                    (Some(&target), _) if is_jump && idx == tokens.len() - 1 => {
+                        (target as isize - here as isize).to_string()
+                    }
+                    (_, Some(value)) if idx > 0 => value.to_string(),
+                    _ => token.clone(),
+                })
+                .collect();
+            let instr = resolved.join(" ").parse().map_err(|err| format!("line {}: {}", line_no, err))?;
+            instructions.push(instr);
+        }
+
+        Ok(instructions)
+    }
+}
+
+/// Assembles source with `label:` definitions, `;` or `#` comments, `.const NAME VALUE` and
+/// `.macro NAME PARAMS...` / `.endm` blocks. Jumps may name a label instead of an offset.
+pub fn assemble(source: &str) -> Result<Vec<Instruction>, String> {
+    let mut assembler = Assembler::default();
+    for (idx, line) in source.lines().enumerate() {
+        assembler.feed(idx + 1, line).map_err(|err| format!("line {}: {}", idx + 1, err))?;
+    }
+    assembler.finish()
+}
+
+/// One canonical instruction per line, readable by `parse_instr` and `assemble`.
+pub fn disassemble(instructions: &[Instruction]) -> String {
+    instructions.iter().map(|instr| instr.to_string()).collect::<Vec<String>>().join("\n")
+}
+
 #[aoc_generator(day10)]
 fn parse_instr(input: &str) -> Vec<Instruction> {
     let mut instructions: Vec<Instruction> = Vec::new();
@@ -77,64 +774,313 @@ fn parse_instr(input: &str) -> Vec<Instruction> {
     instructions
 }
 
+// Lookup of X after every completed cycle, for when random access is handier than streaming.
+#[cfg(test)]
 fn run_cpu(cpu: &mut CPU, instructions: &[Instruction]) -> HashMap<usize, isize> {
-    let mut counts: HashMap<usize, isize> = HashMap::from([(0, 1)]);
-    for instr in instructions {
-        cpu.do_instr(instr).iter().for_each(|(&cycle, &regx)| {
-            counts.insert(cycle, regx);
-        });
+    let mut counts: HashMap<usize, isize> = HashMap::from([(cpu.cycle, cpu.register(Register::X))]);
+    for state in cpu.cycles(instructions) {
+        counts.insert(state.cycle, state.x_after);
     }
 
     counts
 }
 
-fn sum_signal_strengths(indices: &[usize], cycle_reg_x_map: &HashMap<usize, isize>) -> isize {
-    indices
-        .iter()
-        .map(|&idx| isize::try_from(idx).unwrap() * cycle_reg_x_map.get(&(idx - 1)).unwrap())
+fn sum_signal_strengths<I: IntoIterator<Item = CycleState>>(indices: &[usize], states: I) -> isize {
+    let last = indices.iter().max().copied().unwrap_or(0);
+    states
+        .into_iter()
+        .take_while(|state| state.cycle <= last)
+        .filter(|state| indices.contains(&state.cycle))
+        .map(|state| isize::try_from(state.cycle).unwrap() * state.x_during)
         .sum()
 }
 
-fn draw_crt(cycle_reg_x_map: &HashMap<usize, isize>) -> String {
-    let mut crt = String::new();
-    let cycle_idx = |col: usize, row: usize| col + row * 40;
-    for row in 0..6 {
-        for col in 0..40 {
-            let cycle_count = cycle_idx(col, row);
-            let x_reg = cycle_reg_x_map.get(&cycle_count).unwrap();
-            if ((x_reg - 1)..=(x_reg + 1)).contains(&isize::try_from(col).unwrap()) {
-                crt.push('#');
-                continue;
+/// A display the CPU draws on one pixel per cycle, left to right and top to bottom. A pixel lights
+/// when the sprite, `sprite_width` pixels wide and centred on X, covers the beam's column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Crt {
+    width: usize,
+    height: usize,
+    sprite_width: usize,
+    on: char,
+    off: char,
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Crt::new(40, 6)
+    }
+}
+
+impl Crt {
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width > 0 && height > 0, "A CRT needs at least one row and one column");
+        Crt {
+            width,
+            height,
+            sprite_width: 3,
+            on: '#',
+            off: '.',
+        }
+    }
+
+    pub fn sprite_width(mut self, sprite_width: usize) -> Self {
+        self.sprite_width = sprite_width;
+        self
+    }
+
+    pub fn pixels(mut self, on: char, off: char) -> Self {
+        self.on = on;
+        self.off = off;
+        self
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Whether the sprite centred on `x_reg` covers column `col`. Even widths lean right.
+    pub fn sprite_covers(&self, x_reg: isize, col: usize) -> bool {
+        // wide enough that a sprite around any X still has both its ends
+        let (x_reg, col, sprite_width) = (x_reg as i128, col as i128, self.sprite_width as i128);
+        let start = x_reg - (sprite_width - 1) / 2;
+        (start..start + sprite_width).contains(&col)
+    }
+
+    /// Lit pixels for a run. If the program ends early the CPU idles and X holds its final value.
+    pub fn scan<I: IntoIterator<Item = CycleState>>(&self, states: I) -> Grid<bool> {
+        let mut states = states.into_iter();
+        let mut x_idle = 1;
+        let mut pixels = Grid::new(self.width, self.height, false);
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let x_reg = match states.next() {
+                    Some(state) => {
+                        x_idle = state.x_after;
+                        state.x_during
+                    }
+                    None => x_idle,
+                };
+                pixels[(col, row)] = self.sprite_covers(x_reg, col);
             }
-            crt.push('.');
         }
-        crt.push('\n');
+
+        pixels
     }
 
-    crt
+    pub fn draw<I: IntoIterator<Item = CycleState>>(&self, states: I) -> String {
+        self.scan(states).render(|&lit| if lit { self.on } else { self.off })
+    }
+
+    /// Plain PBM image of the run, with lit pixels black.
+    pub fn to_pbm<I: IntoIterator<Item = CycleState>>(&self, states: I) -> String {
+        let pixels = self.scan(states);
+        let mut out = format!("P1\n{} {}\n", pixels.width(), pixels.height());
+        for row in pixels.rows() {
+            let values: Vec<&str> = row.iter().map(|&lit| if lit { "1" } else { "0" }).collect();
+            out.push_str(&values.join(" "));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl Crt {
+    /// Finds an `addx`/`noop` program that draws `target`, in this CRT's on and off characters.
+    /// X only changes when an `addx` completes, so it can never change on two cycles in a row;
+    /// this walks every X the sprite could usefully be at, cycle by cycle, keeping the ones that
+    /// match the image so far.
+    pub fn synthesize(&self, target: &str) -> Result<Vec<Instruction>, String> {
+        let image = Grid::parse(target, |c| match c {
+            _ if c == self.on => Ok(true),
+            _ if c == self.off => Ok(false),
+            _ => Err(format!("Unexpected pixel {:?}", c)),
+        })?;
+        if (image.width(), image.height()) != (self.width, self.height) {
+            return Err(format!(
+                "Expected a {}x{} image, got {}x{}",
+                self.width,
+                self.height,
+                image.width(),
+                image.height()
+            ));
+        }
+
+        // X this far off screen lights nothing, the same as any X further out
+        let margin = isize::try_from(self.sprite_width).unwrap();
+        let lowest = -margin;
+        let xs: Vec<isize> = (lowest..=isize::try_from(self.width).unwrap() + margin).collect();
+        let slot = |x: isize| usize::try_from(x - lowest).unwrap();
+        let total = image.len();
+        let draws = |cycle: usize, x: isize| {
+            let (col, row) = image.coords_of(cycle);
+            self.sprite_covers(x, col) == image[(col, row)]
+        };
+
+        // how each (cycle, X) was first reached: the X before it and whether it took an addx
+        let mut reached: Vec<Vec<Option<(isize, bool)>>> = vec![vec![None; xs.len()]; total + 1];
+        reached[0][slot(1)] = Some((1, false));
+        for cycle in 0..total {
+            for &x in &xs {
+                if reached[cycle][slot(x)].is_none() || !draws(cycle, x) {
+                    continue;
+                }
+                reached[cycle + 1][slot(x)].get_or_insert((x, false));
+                if cycle + 2 <= total && draws(cycle + 1, x) {
+                    for &next in &xs {
+                        reached[cycle + 2][slot(next)].get_or_insert((x, true));
+                    }
+                }
+            }
+        }
+
+        let Some(mut x) = xs.iter().copied().find(|&x| reached[total][slot(x)].is_some()) else {
+            // the furthest cycle any program got to is the pixel none of them can draw
+            let stuck = (0..total)
+                .rev()
+                .find(|&cycle| reached[cycle].iter().any(Option::is_some))
+                .unwrap_or(0);
+            let (col, row) = image.coords_of(stuck);
+            return Err(format!("No program can draw pixel ({}, {})", col, row));
+        };
+        let mut program = Vec::new();
+        let mut cycle = total;
+        while cycle > 0 {
+            let (prev, addx) = reached[cycle][slot(x)].unwrap();
+            if addx {
+                program.push(Instruction::ADDX(x - prev));
+                cycle -= 2;
+            } else {
+                program.push(Instruction::NOOP);
+                cycle -= 1;
+            }
+            x = prev;
+        }
+        program.reverse();
+
+        Ok(program)
+    }
+}
+
+fn draw_crt<I: IntoIterator<Item = CycleState>>(states: I) -> String {
+    Crt::default().draw(states)
+}
+
+/// The 4x6 letter font the CRT draws in, one glyph per 5 columns.
+const FONT: [(char, [&str; 6]); 17] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_STRIDE: usize = 5;
+
+/// Reads the letters off a CRT drawing. Blank glyphs read as spaces, anything else not in the
+/// font is reported by the column it starts at, as is any lit pixel between or after glyphs.
+pub fn read_crt(crt: &str) -> Result<String, String> {
+    let rows: Vec<&str> = crt.lines().filter(|line| !line.is_empty()).collect();
+    if rows.len() != 6 {
+        return Err(format!("Expected 6 rows, got {}", rows.len()));
+    }
+    let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let mut text = String::new();
+    let mut unknown = Vec::new();
+    let mut lit_gaps = Vec::new();
+    for col in (0..width).step_by(GLYPH_STRIDE) {
+        for gap in col + GLYPH_WIDTH..(col + GLYPH_STRIDE).min(width) {
+            if rows.iter().any(|row| row.as_bytes().get(gap).is_some_and(|&pixel| pixel != b'.')) {
+                lit_gaps.push(gap.to_string());
+            }
+        }
+        let glyph: Vec<&str> = rows
+            .iter()
+            .map(|row| row.get(col..(col + GLYPH_WIDTH).min(row.len())).unwrap_or(""))
+            .collect();
+        if glyph.iter().all(|line| line.chars().all(|c| c == '.')) {
+            text.push(' ');
+        } else if let Some((letter, _)) = FONT.iter().find(|(_, lines)| lines[..] == glyph[..]) {
+            text.push(*letter);
+        } else {
+            unknown.push(col.to_string());
+        }
+    }
+    if !unknown.is_empty() {
+        return Err(format!("Unknown glyphs at columns {}", unknown.join(", ")));
+    }
+    if !lit_gaps.is_empty() {
+        return Err(format!("Lit pixels between glyphs at columns {}", lit_gaps.join(", ")));
+    }
+
+    Ok(text.trim_end().to_owned())
+}
+
+/// Draws `text` in the CRT font, the inverse of `read_crt`.
+pub fn render_text(text: &str) -> Result<String, String> {
+    let mut rows = vec![String::new(); 6];
+    for (idx, letter) in text.chars().enumerate() {
+        let lines = match letter {
+            ' ' => ["...."; 6],
+            _ => FONT
+                .iter()
+                .find(|(c, _)| *c == letter)
+                .map(|(_, lines)| *lines)
+                .ok_or(format!("No glyph for {:?}", letter))?,
+        };
+        for (row, line) in rows.iter_mut().zip(lines) {
+            if idx > 0 {
+                row.push('.');
+            }
+            row.push_str(line);
+        }
+    }
+
+    Ok(rows.iter().map(|row| format!("{}\n", row)).collect())
 }
 
 #[aoc(day10, part1)]
 fn get_signal_strengh_sum(instructions: &[Instruction]) -> isize {
     sum_signal_strengths(
         &[20, 60, 100, 140, 180, 220],
-        &run_cpu(&mut CPU::new(), instructions),
+        CPU::new().cycles(instructions),
     )
 }
 
 #[aoc(day10, part2)]
 fn get_crt_display(instructions: &[Instruction]) -> String {
-    let mut crt = draw_crt(&run_cpu(&mut CPU::new(), instructions));
+    let mut crt = draw_crt(CPU::new().cycles(instructions));
     crt.insert(0, '\n');
 
     crt
 }
 
+#[aoc(day10, part2, ocr)]
+fn read_crt_display(instructions: &[Instruction]) -> String {
+    read_crt(&draw_crt(CPU::new().cycles(instructions))).expect("CRT should show a known word")
+}
+
 #[cfg(test)]
 mod test {
     use std::collections::HashMap;
 
-    use crate::day10::{CPU, draw_crt, Instruction, parse_instr, run_cpu, sum_signal_strengths};
+    use crate::day10::{assemble, CPU, Breakpoint, Crt, disassemble, CycleCosts, CycleState, Debugger, draw_crt, Stop, trace_cpu, Instruction, read_crt, render_text, Opcode, Operand, parse_instr, Register, run_cpu, sum_signal_strengths};
 
     const CYCLE_EXAMPLE: &str = "noop
 addx 3
@@ -321,10 +1267,10 @@ noop";
     #[test]
     fn test_signal_strength() {
         let instructions = parse_instr(SIGNAL_STR_EXAMPLE);
-        let counts = run_cpu(&mut CPU::new(), &instructions);
+        let mut cpu = CPU::new();
         let cycle_indices: [usize; 6] = [20, 60, 100, 140, 180, 220];
 
-        let sum: isize = sum_signal_strengths(&cycle_indices, &counts);
+        let sum: isize = sum_signal_strengths(&cycle_indices, cpu.cycles(&instructions));
 
         assert_eq!(13140, sum);
     }
@@ -332,9 +1278,436 @@ noop";
     #[test]
     fn test_crt() {
         let instructions = parse_instr(SIGNAL_STR_EXAMPLE);
-        let reg_map = run_cpu(&mut CPU::new(), &instructions);
-        let crt = draw_crt(&reg_map);
+        let crt = draw_crt(CPU::new().cycles(&instructions));
 
         assert_eq!(CRT_EXAMPLE, crt);
     }
+
+    #[test]
+    fn test_extended_instruction_from_string() {
+        let instructions = parse_instr("mov a 3\nadd x a\nsub b -2\nmul a b\njmp -4\njz c 2\njnz a -1");
+
+        assert_eq!(
+            vec![
+                Instruction::MOV(Register::A, Operand::Imm(3)),
+                Instruction::ADD(Register::X, Operand::Reg(Register::A)),
+                Instruction::SUB(Register::B, Operand::Imm(-2)),
+                Instruction::MUL(Register::A, Operand::Reg(Register::B)),
+                Instruction::JMP(-4),
+                Instruction::JZ(Register::C, 2),
+                Instruction::JNZ(Register::A, -1),
+            ],
+            instructions
+        );
+        assert!("mov y 1".parse::<Instruction>().is_err());
+        assert!("addx".parse::<Instruction>().is_err());
+    }
+
+    #[test]
+    fn test_loop_with_registers() {
+        // adds 2 to x three times using a as the loop counter
+        let instructions = parse_instr("mov a 3\naddx 2\nsub a 1\njnz a -2\nnoop");
+        let mut cpu = CPU::new();
+        let counts = run_cpu(&mut cpu, &instructions);
+
+        assert_eq!(7, cpu.register(Register::X));
+        assert_eq!(0, cpu.register(Register::A));
+        assert_eq!(1 + 3 * (2 + 1 + 1) + 1, cpu.cycle);
+        assert_eq!(Some(&3), counts.get(&3));
+        assert_eq!(Some(&7), counts.get(&cpu.cycle));
+    }
+
+    #[test]
+    fn test_registers_wrap() {
+        // squares a forever: 2, 4, 16, 256, 65536, 2^32, then 0 once it passes 2^64
+        let instructions = parse_instr("mov a 2\nmul a a\njmp -1");
+        let mut cpu = CPU::new();
+        cpu.cycles(&instructions).take(100).for_each(drop);
+
+        assert_eq!(0, cpu.register(Register::A));
+
+        let instructions = parse_instr(&format!("mov a {}\nadd a 1\nsub a 1", isize::MAX));
+        let mut cpu = CPU::new();
+        run_cpu(&mut cpu, &instructions);
+        assert_eq!(isize::MAX, cpu.register(Register::A));
+
+        let instructions = parse_instr(&format!("noop\njmp {}", isize::MAX));
+        let mut cpu = CPU::new();
+        run_cpu(&mut cpu, &instructions);
+        assert_eq!(usize::MAX, cpu.pc);
+    }
+
+    #[test]
+    fn test_cycle_costs() {
+        let costs = CycleCosts::default().with(Opcode::ADDX, 3).with(Opcode::NOOP, 2);
+        let instructions = parse_instr(CYCLE_EXAMPLE);
+        let mut cpu = CPU::with_costs(costs);
+        let counts = run_cpu(&mut cpu, &instructions);
+
+        assert_eq!(8, cpu.cycle);
+        assert_eq!(Some(&1), counts.get(&4));
+        assert_eq!(Some(&4), counts.get(&5));
+        assert_eq!(Some(&-1), counts.get(&8));
+    }
+
+    #[test]
+    fn test_cycle_states() {
+        let instructions = parse_instr(CYCLE_EXAMPLE);
+        let mut cpu = CPU::new();
+        let states: Vec<CycleState> = cpu.cycles(&instructions).collect();
+
+        assert_eq!(
+            vec![
+                CycleState { cycle: 1, x_during: 1, x_after: 1 },
+                CycleState { cycle: 2, x_during: 1, x_after: 1 },
+                CycleState { cycle: 3, x_during: 1, x_after: 4 },
+                CycleState { cycle: 4, x_during: 4, x_after: 4 },
+                CycleState { cycle: 5, x_during: 4, x_after: -1 },
+            ],
+            states
+        );
+    }
+
+    #[test]
+    fn test_long_run_streams() {
+        let instructions = parse_instr("mov a 300000\naddx 1\nsub a 1\njnz a -2");
+        let mut cpu = CPU::new();
+        let lit = cpu.cycles(&instructions).filter(|state| state.x_after > state.x_during).count();
+
+        assert_eq!(300_000, lit);
+        assert_eq!(1 + 300_000 * 4, cpu.cycle);
+    }
+
+    #[test]
+    fn test_crt_idles_after_short_program() {
+        let crt = draw_crt(CPU::new().cycles(&parse_instr("addx 20")));
+
+        assert!(crt.starts_with("##..................###.................\n"));
+    }
+
+    #[test]
+    fn test_debugger_breakpoints() {
+        let instructions = parse_instr(SIGNAL_STR_EXAMPLE);
+        let mut cpu = CPU::new();
+        let mut debugger = Debugger::new(&mut cpu, &instructions);
+        debugger.add_breakpoint(Breakpoint::Cycle(20));
+        debugger.add_breakpoint(Breakpoint::Instruction(9));
+        debugger.add_breakpoint(Breakpoint::Register(Register::X, -1));
+
+        match debugger.resume() {
+            Stop::Breakpoint(Breakpoint::Instruction(9), state) => assert_eq!(18, state.cycle),
+            stop => panic!("Unexpected stop {:?}", stop),
+        }
+        match debugger.resume() {
+            Stop::Breakpoint(Breakpoint::Cycle(20), state) => assert_eq!(21, state.x_during),
+            stop => panic!("Unexpected stop {:?}", stop),
+        }
+        assert!(debugger.remove_breakpoint(Breakpoint::Instruction(9)));
+        assert!(debugger.remove_breakpoint(Breakpoint::Register(Register::X, -1)));
+        assert_eq!(Stop::Finished, debugger.resume());
+        assert_eq!(240, debugger.cpu().cycle());
+    }
+
+    #[test]
+    fn test_debugger_breaks_before_first_instruction() {
+        let instructions = parse_instr("noop\nnoop\njmp -2");
+        let mut cpu = CPU::new();
+        let mut debugger = Debugger::new(&mut cpu, &instructions);
+        debugger.add_breakpoint(Breakpoint::Instruction(0));
+
+        match debugger.resume() {
+            Stop::Breakpoint(Breakpoint::Instruction(0), state) => assert_eq!(0, state.cycle),
+            stop => panic!("Unexpected stop {:?}", stop),
+        }
+        assert_eq!(0, debugger.cpu().cycle());
+        // resuming runs the loop around once and lands on the first noop again
+        match debugger.resume() {
+            Stop::Breakpoint(Breakpoint::Instruction(0), state) => assert_eq!(3, state.cycle),
+            stop => panic!("Unexpected stop {:?}", stop),
+        }
+    }
+
+    #[test]
+    fn test_debugger_never_breaks_past_the_end() {
+        let instructions = parse_instr("noop");
+        let mut cpu = CPU::new();
+        let mut debugger = Debugger::new(&mut cpu, &instructions);
+        debugger.add_breakpoint(Breakpoint::Instruction(1));
+
+        assert_eq!(Stop::Finished, debugger.resume());
+    }
+
+    #[test]
+    fn test_breakpoint_display() {
+        for bp in ["cycle 20", "instr 0", "reg x -4"] {
+            assert_eq!(bp, bp.parse::<Breakpoint>().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_debugger_script() {
+        let instructions = parse_instr(CYCLE_EXAMPLE);
+        let mut cpu = CPU::new();
+        let mut debugger = Debugger::new(&mut cpu, &instructions);
+        let script = "# stop when addx 3 lands
+break reg x 4
+continue
+print x
+step 2
+regs
+continue";
+
+        assert_eq!(
+            "breakpoint reg x 4
+hit reg x 4: cycle 3: x = 1 -> 4
+x = 4
+cycle 4: x = 4 -> 4
+cycle 5: x = 4 -> -1
+x = -1, a = 0, b = 0, c = 0, cycle = 5, pc = 3
+finished at cycle 5
+",
+            debugger.run_script(script).unwrap()
+        );
+        assert_eq!(Err("line 1: Unknown command jump".to_owned()), debugger.run_script("jump 3"));
+    }
+
+    #[test]
+    fn test_debugger_repl() {
+        let instructions = parse_instr(CYCLE_EXAMPLE);
+        let mut cpu = CPU::new();
+        let mut debugger = Debugger::new(&mut cpu, &instructions);
+        let mut output = Vec::new();
+        debugger.repl("print y\nstep\nprint cycle\n".as_bytes(), &mut output).unwrap();
+
+        assert_eq!(
+            "error: Unknown register y\ncycle 1: x = 1 -> 1\ncycle = 1\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_trace_cpu() {
+        let instructions = parse_instr(CYCLE_EXAMPLE);
+        let mut vcd = Vec::new();
+        let cycles = trace_cpu(&mut CPU::new(), &instructions, &Crt::default(), &mut vcd).unwrap();
+
+        assert_eq!(5, cycles);
+        assert_eq!(
+            "$version aoc22 day10 $end
+$timescale 1ns $end
+$scope module cpu $end
+$var integer 64 ! x $end
+$var integer 32 \" cycle $end
+$var integer 32 # pc $end
+$var string 1 $ instruction $end
+$var integer 32 % beam_column $end
+$upscope $end
+$enddefinitions $end
+#0
+b1 !
+b1 \"
+b0 #
+snoop $
+b0 %
+#1
+b10 \"
+b1 #
+saddx_3 $
+b1 %
+#2
+b11 \"
+b10 %
+#3
+b100 !
+b100 \"
+b10 #
+saddx_-5 $
+b11 %
+#4
+b101 \"
+b100 %
+#5
+b1111111111111111111111111111111111111111111111111111111111111111 !
+",
+            String::from_utf8(vcd).unwrap()
+        );
+
+        let mut vcd = Vec::new();
+        trace_cpu(&mut CPU::new(), &instructions, &Crt::new(2, 3), &mut vcd).unwrap();
+        let vcd = String::from_utf8(vcd).unwrap();
+        let beam: Vec<&str> = vcd.lines().filter_map(|line| line.strip_suffix(" %")).collect();
+        // a 2 wide screen wraps the beam every other cycle
+        assert_eq!(vec!["b0", "b1", "b0", "b1", "b0"], beam);
+    }
+
+    #[test]
+    fn test_read_crt() {
+        let crt = render_text("RZEKEFHA").unwrap();
+
+        assert_eq!(6, crt.lines().count());
+        assert_eq!(39, crt.lines().next().unwrap().len());
+        assert_eq!(Ok("RZEKEFHA".to_owned()), read_crt(&crt));
+        assert_eq!(Ok("HI JO".to_owned()), read_crt(&render_text("HI JO").unwrap()));
+        assert_eq!(Err("No glyph for 'Q'".to_owned()), render_text("Q"));
+
+        // a full screen as the CRT draws it, trailing spacer column included
+        let screen = "\
+####.#..#.####.####.####.#..#..##..####.
+#....#..#....#.#.......#.#..#.#..#....#.
+###..####...#..###....#..####.#......#..
+#....#..#..#...#.....#...#..#.#.....#...
+#....#..#.#....#....#....#..#.#..#.#....
+####.#..#.####.#....####.#..#..##..####.
+";
+        assert_eq!(Ok("EHZFZHCZ".to_owned()), read_crt(screen));
+    }
+
+    #[test]
+    fn test_read_crt_unknown_glyphs() {
+        assert_eq!(
+            Err("Unknown glyphs at columns 0, 5, 10, 15, 20, 25, 30, 35".to_owned()),
+            read_crt(CRT_EXAMPLE)
+        );
+        let mut crt = render_text("ABC").unwrap();
+        // fill in the gap at the top right of the B
+        crt.replace_range(8..9, "#");
+        assert_eq!(Err("Unknown glyphs at columns 5".to_owned()), read_crt(&crt));
+        let mut crt = render_text("EH").unwrap();
+        // light the gap between the E and the H
+        crt.replace_range(4..5, "#");
+        assert_eq!(Err("Lit pixels between glyphs at columns 4".to_owned()), read_crt(&crt));
+        // and the trailing column after the last glyph
+        let crt: String = render_text("EH").unwrap().lines().map(|row| format!("{}#\n", row)).collect();
+        assert_eq!(Err("Lit pixels between glyphs at columns 9".to_owned()), read_crt(&crt));
+        assert_eq!(Err("Expected 6 rows, got 1".to_owned()), read_crt("####"));
+    }
+
+    #[test]
+    fn test_crt_geometry() {
+        let instructions = parse_instr(SIGNAL_STR_EXAMPLE);
+        let default_crt = Crt::default().draw(CPU::new().cycles(&instructions));
+        assert_eq!(CRT_EXAMPLE, default_crt);
+
+        // X runs 1, 1, 16, 16, 5, 5, 11, 11 then 8, 8, 13, 13, 12, 12, 4, 4
+        let narrow = Crt::new(8, 1).sprite_width(1).pixels('X', ' ');
+        assert_eq!(" X   X  \n", narrow.draw(CPU::new().cycles(&instructions)));
+        let wide = Crt::new(8, 2).sprite_width(5);
+        assert_eq!("##..##..\n......#.\n", wide.draw(CPU::new().cycles(&instructions)));
+
+        assert!(Crt::default().sprite_covers(5, 4) && Crt::default().sprite_covers(5, 6));
+        assert!(!Crt::default().sprite_covers(5, 7));
+        let even = Crt::default().sprite_width(2);
+        assert!(even.sprite_covers(5, 5) && even.sprite_covers(5, 6) && !even.sprite_covers(5, 4));
+
+        for x in [isize::MAX, isize::MIN] {
+            let crt = draw_crt(CPU::new().cycles(&parse_instr(&format!("mov x {}\nnoop", x))));
+            // only the first cycle still sees X at 1
+            assert_eq!(Some("#......................................."), crt.lines().next());
+            assert!(crt.lines().skip(1).all(|row| !row.contains('#')));
+        }
+        assert!(Crt::default().sprite_width(usize::MAX).sprite_covers(isize::MAX, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "A CRT needs at least one row and one column")]
+    fn test_crt_needs_pixels() {
+        let mut vcd = Vec::new();
+        trace_cpu(&mut CPU::new(), &parse_instr("noop"), &Crt::new(0, 6), &mut vcd).unwrap();
+    }
+
+    #[test]
+    fn test_crt_pbm() {
+        let instructions = parse_instr(CYCLE_EXAMPLE);
+        let pbm = Crt::new(4, 2).to_pbm(CPU::new().cycles(&instructions));
+
+        assert_eq!("P1\n4 2\n1 1 1 1\n0 0 0 0\n", pbm);
+    }
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+; counts a down from START, doubling b each time
+.const START 3
+.macro double reg
+    mul reg 2
+.endm
+
+    mov a START
+    mov b 1   # b ends up as 2^START
+loop:
+    double b
+    sub a 1
+    jnz a loop
+    jmp end
+    noop
+end: addx START
+";
+        let instructions = assemble(source).unwrap();
+
+        assert_eq!(vec![
+            Instruction::MOV(Register::A, Operand::Imm(3)),
+            Instruction::MOV(Register::B, Operand::Imm(1)),
+            Instruction::MUL(Register::B, Operand::Imm(2)),
+            Instruction::SUB(Register::A, Operand::Imm(1)),
+            Instruction::JNZ(Register::A, -2),
+            Instruction::JMP(2),
+            Instruction::NOOP,
+            Instruction::ADDX(3),
+        ], instructions);
+
+        let mut cpu = CPU::new();
+        cpu.cycles(&instructions).for_each(drop);
+        assert_eq!(8, cpu.register(Register::B));
+        assert_eq!(4, cpu.register(Register::X));
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(Err("line 1: Could not parse noopx".to_owned()), assemble("noopx"));
+        assert_eq!(Err("line 2: Could not parse noop x".to_owned()), assemble("noop\nnoop x"));
+        assert_eq!(Err("line 1: Expected a number, got nowhere".to_owned()), assemble("jmp nowhere"));
+        assert_eq!(Err("line 3: Duplicate label a".to_owned()), assemble("a:\nnoop\na: noop"));
+        assert_eq!(Err("line 1: Constant x shadows a register".to_owned()), assemble(".const x 1"));
+        assert_eq!(Err("Macro m is missing .endm".to_owned()), assemble(".macro m\nnoop"));
+        assert_eq!(Err("line 3: Macro m takes 1 arguments, got 0".to_owned()), assemble(".macro m n\n.endm\nm"));
+        assert_eq!(Err("line 4: Macros nested too deeply".to_owned()), assemble(".macro m\nm\n.endm\nm"));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let instructions = parse_instr(SIGNAL_STR_EXAMPLE);
+
+        assert_eq!(SIGNAL_STR_EXAMPLE, disassemble(&instructions));
+        let extended = vec![
+            Instruction::MOV(Register::C, Operand::Reg(Register::X)),
+            Instruction::JZ(Register::A, -4),
+        ];
+        assert_eq!("mov c x\njz a -4", disassemble(&extended));
+        assert_eq!(extended, assemble(&disassemble(&extended)).unwrap());
+    }
+
+    #[test]
+    fn test_synthesize() {
+        let crt = Crt::default();
+        for target in [CRT_EXAMPLE.to_owned(), render_text("RZEKEFHA").unwrap().replace('\n', ".\n")] {
+            let program = crt.synthesize(&target).unwrap();
+
+            assert!(program.iter().all(|instr| matches!(instr, Instruction::NOOP | Instruction::ADDX(_))));
+            assert_eq!(target, draw_crt(CPU::new().cycles(&program)));
+        }
+        assert_eq!(Err("Expected a 40x6 image, got 2x1".to_owned()), crt.synthesize("##"));
+    }
+
+    #[test]
+    fn test_synthesize_impossible() {
+        let crt = Crt::new(4, 1);
+
+        let program = crt.synthesize("##.#").unwrap();
+        assert_eq!("##.#\n", crt.draw(CPU::new().cycles(&program)));
+
+        // X starts at 1, and can't change on two cycles in a row
+        assert_eq!(Err("No program can draw pixel (0, 0)".to_owned()), crt.synthesize(".#.."));
+        assert_eq!(Err("No program can draw pixel (1, 0)".to_owned()), crt.synthesize("#.#."));
+        assert_eq!(Err("(1, 0): Unexpected pixel 'x'".to_owned()), crt.synthesize("#x.."));
+    }
 }
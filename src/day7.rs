@@ -1,114 +1,953 @@
 use aoc_runner_derive::aoc_generator;
 use regex::Regex;
 
-use std::cell::RefCell;
 use std::collections::HashMap;
-use std::ops::Deref;
-use std::rc::Rc;
+use std::fs;
+use std::io;
+use std::path;
+
+pub type NodeId = usize;
 
 pub enum Path {
   File(usize),
-  Folder(HashMap<String, Rc<RefCell<Path>>>),
+  Folder(HashMap<String, NodeId>),
+}
+
+struct Node {
+  name: String,
+  parent: Option<NodeId>,
+  path: Path,
+  size: usize,
 }
 
-impl Path {
-  fn new_folder() -> Rc<RefCell<Self>> {
-    Rc::new(RefCell::new(Self::Folder(HashMap::new())))
+pub struct FileSystem {
+  nodes: Vec<Node>,
+}
+
+impl FileSystem {
+  pub const ROOT: NodeId = 0;
+
+  pub fn new() -> Self {
+    FileSystem {
+      nodes: vec![Node {
+        name: "/".to_owned(),
+        parent: None,
+        path: Path::Folder(HashMap::new()),
+        size: 0,
+      }],
+    }
+  }
+
+  fn push_child(&mut self, parent: NodeId, name: &str, path: Path) -> NodeId {
+    let id = self.nodes.len();
+    match &mut self.nodes[parent].path {
+      Path::File(_) => panic!("Cannot add node to a file"),
+      Path::Folder(children) => children.insert(name.to_owned(), id),
+    };
+    self.nodes.push(Node {
+      name: name.to_owned(),
+      parent: Some(parent),
+      path,
+      size: 0,
+    });
+    id
   }
 
-  fn add_file(&mut self, name: &str, size: usize) {
-    match self {
-      Self::File(_) => panic!("Cannot add node to a file"),
-      Self::Folder(children) => {
-        children.insert(name.to_owned(), Rc::new(RefCell::new(Self::File(size))))
-      }
+  pub fn child(&self, parent: NodeId, name: &str) -> Option<NodeId> {
+    match &self.nodes[parent].path {
+      Path::File(_) => None,
+      Path::Folder(children) => children.get(name).copied(),
+    }
+  }
+
+  fn grow(&mut self, from: NodeId, old_size: usize, new_size: usize) {
+    let mut cur = Some(from);
+    while let Some(id) = cur {
+      let node = &mut self.nodes[id];
+      node.size = node.size - old_size + new_size;
+      cur = node.parent;
+    }
+  }
+
+  pub fn add_file(&mut self, parent: NodeId, name: &str, size: usize) -> NodeId {
+    let id = match self.child(parent, name) {
+      Some(id) => match self.nodes[id].path {
+        Path::File(_) => id,
+        Path::Folder(_) => panic!("Cannot replace a folder with a file"),
+      },
+      None => self.push_child(parent, name, Path::File(0)),
+    };
+    let old_size = self.nodes[id].size;
+    self.nodes[id].path = Path::File(size);
+    self.grow(id, old_size, size);
+    id
+  }
+
+  pub fn get_folder(&mut self, parent: NodeId, name: &str) -> NodeId {
+    match self.child(parent, name) {
+      Some(id) => match self.nodes[id].path {
+        Path::File(_) => panic!("Cannot retrieve a folder from file"),
+        Path::Folder(_) => id,
+      },
+      None => self.push_child(parent, name, Path::Folder(HashMap::new())),
+    }
+  }
+
+  pub fn path(&self, id: NodeId) -> &Path {
+    &self.nodes[id].path
+  }
+
+  pub fn name(&self, id: NodeId) -> &str {
+    &self.nodes[id].name
+  }
+
+  pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+    self.nodes[id].parent
+  }
+
+  pub fn get_size(&self, id: NodeId) -> usize {
+    self.nodes[id].size
+  }
+
+  pub fn is_folder(&self, id: NodeId) -> bool {
+    matches!(self.nodes[id].path, Path::Folder(_))
+  }
+
+  pub fn children(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+    let children = match &self.nodes[id].path {
+      Path::File(_) => None,
+      Path::Folder(children) => Some(children.values().copied()),
     };
+    children.into_iter().flatten()
+  }
+
+  pub fn folders(&self) -> impl Iterator<Item = NodeId> + '_ {
+    (0..self.nodes.len()).filter(|&id| self.is_folder(id))
+  }
+
+  pub fn folder_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+    self.folders().map(|id| self.get_size(id))
+  }
+
+  pub fn depth(&self, id: NodeId) -> usize {
+    let mut depth = 0;
+    let mut cur = self.parent(id);
+    while let Some(parent) = cur {
+      depth += 1;
+      cur = self.parent(parent);
+    }
+    depth
+  }
+
+  pub fn full_path(&self, id: NodeId) -> String {
+    let mut names = Vec::new();
+    let mut cur = id;
+    while let Some(parent) = self.parent(cur) {
+      names.push(self.name(cur));
+      cur = parent;
+    }
+    names.reverse();
+    format!("/{}", names.join("/"))
+  }
+
+  pub fn sorted_children(&self, id: NodeId, sort: SortBy) -> Vec<NodeId> {
+    let mut children: Vec<NodeId> = self.children(id).collect();
+    match sort {
+      SortBy::Name => children.sort_by(|a, b| self.name(*a).cmp(self.name(*b))),
+      SortBy::Size => children.sort_by(|a, b| {
+        self.get_size(*b)
+            .cmp(&self.get_size(*a))
+            .then_with(|| self.name(*a).cmp(self.name(*b)))
+      }),
+    }
+    children
+  }
+
+  /// Renders the tree the way `tree -s` would, with the size of every entry in brackets.
+  pub fn render_tree(&self, sort: SortBy) -> String {
+    let mut out = format!("[{}]  {}\n", self.get_size(Self::ROOT), self.name(Self::ROOT));
+    self.render_subtree(Self::ROOT, sort, "", &mut out);
+    out
+  }
+
+  fn render_subtree(&self, id: NodeId, sort: SortBy, prefix: &str, out: &mut String) {
+    let children = self.sorted_children(id, sort);
+    for (idx, &child) in children.iter().enumerate() {
+      let last = idx + 1 == children.len();
+      let branch = if last { "└── " } else { "├── " };
+      out.push_str(&format!("{}{}[{}]  {}\n", prefix, branch, self.get_size(child), self.name(child)));
+      let indent = if last { "    " } else { "│   " };
+      self.render_subtree(child, sort, &format!("{}{}", prefix, indent), out);
+    }
+  }
+
+  /// Returns the full path and size of every folder at most `max_depth` below the root.
+  pub fn du_entries(&self, max_depth: usize, sort: SortBy) -> Vec<(String, usize)> {
+    let mut entries: Vec<(String, usize)> = self.folders()
+        .filter(|&id| self.depth(id) <= max_depth)
+        .map(|id| (self.full_path(id), self.get_size(id)))
+        .collect();
+    match sort {
+      SortBy::Name => entries.sort(),
+      SortBy::Size => entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0))),
+    }
+    entries
+  }
+
+  /// Renders folders like `du -h --max-depth N`, one `size<TAB>path` line each.
+  pub fn render_du(&self, max_depth: usize, sort: SortBy) -> String {
+    self.du_entries(max_depth, sort)
+        .iter()
+        .map(|(path, size)| format!("{}\t{}\n", human_size(*size), path))
+        .collect()
+  }
+
+  pub fn query(&self, query: &Query) -> Vec<NodeId> {
+    let mut found: Vec<NodeId> = (0..self.nodes.len())
+        .filter(|&id| query.matches(self, id))
+        .collect();
+    match query.sort {
+      Some(SortBy::Name) => found.sort_by_cached_key(|&id| self.full_path(id)),
+      Some(SortBy::Size) => found.sort_by(|a, b| {
+        self.get_size(*b)
+            .cmp(&self.get_size(*a))
+            .then_with(|| self.full_path(*a).cmp(&self.full_path(*b)))
+      }),
+      None => {}
+    }
+    if let Some(limit) = query.limit {
+      found.truncate(limit);
+    }
+    found
+  }
+
+  /// Walks a directory on disk into the same tree `parse_input` builds. Symlinks are skipped so
+  /// cycles can't occur.
+  pub fn from_dir(dir: &path::Path) -> io::Result<Self> {
+    let mut fs = FileSystem::new();
+    fs.read_dir_into(Self::ROOT, dir)?;
+    Ok(fs)
+  }
+
+  fn read_dir_into(&mut self, id: NodeId, dir: &path::Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+      let entry = entry?;
+      let file_type = entry.file_type()?;
+      let name = entry.file_name().to_string_lossy().into_owned();
+      if file_type.is_dir() {
+        let folder = self.get_folder(id, &name);
+        self.read_dir_into(folder, &entry.path())?;
+      } else if file_type.is_file() {
+        let size = entry.metadata()?.len();
+        self.add_file(id, &name, usize::try_from(size).unwrap_or(usize::MAX));
+      }
+    }
+    Ok(())
+  }
+
+  /// Emits a `$ cd`/`$ ls` transcript that `parse_input` turns back into this tree.
+  pub fn to_transcript(&self) -> String {
+    let mut out = String::from("$ cd /\n");
+    self.write_transcript(Self::ROOT, &mut out);
+    out
+  }
+
+  fn write_transcript(&self, id: NodeId, out: &mut String) {
+    let children = self.sorted_children(id, SortBy::Name);
+    out.push_str("$ ls\n");
+    for &child in &children {
+      match self.path(child) {
+        Path::File(size) => out.push_str(&format!("{} {}\n", size, self.name(child))),
+        Path::Folder(_) => out.push_str(&format!("dir {}\n", self.name(child))),
+      }
+    }
+    for &child in children.iter().filter(|&&child| self.is_folder(child)) {
+      out.push_str(&format!("$ cd {}\n", self.name(child)));
+      self.write_transcript(child, out);
+      out.push_str("$ cd ..\n");
+    }
+  }
+
+  pub fn space_to_free(&self, disk_size: usize, required: usize) -> usize {
+    (self.get_size(Self::ROOT) + required).saturating_sub(disk_size)
+  }
+
+  pub fn smallest_deletion(&self, disk_size: usize, required: usize) -> Option<NodeId> {
+    let must_free = self.space_to_free(disk_size, required);
+    self.folders()
+        .filter(|&id| self.get_size(id) >= must_free)
+        .min_by_key(|&id| self.get_size(id))
+  }
+
+  /// Picks the non-nested folders whose combined size is the smallest that still frees enough
+  /// space, preferring fewer folders on ties. Folders are decided in preorder, so deleting one
+  /// lands just past its subtree. This is a subset sum bounded by `must_free` kept as bitsets:
+  /// one walk finds the smallest total, then one walk per extra folder finds the fewest folders
+  /// that reach it, so the work is the number of folders times `must_free / 64` per walk.
+  pub fn plan_deletion(&self, disk_size: usize, required: usize) -> Option<Vec<NodeId>> {
+    let must_free = self.space_to_free(disk_size, required);
+    if must_free == 0 {
+      return Some(Vec::new());
+    }
+    let (order, subtree_end) = self.folder_preorder();
+    let sizes: Vec<usize> = order.iter().map(|&id| self.get_size(id)).collect();
+
+    let mut best: Option<usize> = None;
+    Layer::walk(must_free, &sizes, &subtree_end, None, |every, idx| {
+      // past the target only the smallest total matters
+      if let Some(total) = every.first_from(must_free.saturating_sub(sizes[idx])).map(|sum| sum + sizes[idx]) {
+        best = Some(best.map_or(total, |best| best.min(total)));
+      }
+      best == Some(must_free)
+    });
+    let best = best?;
+
+    // layers[k] holds the sums of at most k folders, walked until one more folder reaches `best`
+    let reaches = |layer: &Layer, idx: usize| sizes[idx] <= best && layer.contains(best - sizes[idx]);
+    // the empty sum is never walked, so nothing is born in it
+    let mut layers = vec![Layer { marks: vec![0; sizes.len()], ..Layer::new(must_free) }];
+    let mut last = (0..sizes.len()).find(|&idx| sizes[idx] == best);
+    while last.is_none() {
+      let (layer, found) = Layer::walk(must_free, &sizes, &subtree_end, layers.last(), reaches);
+      layers.push(layer);
+      last = found;
+    }
+
+    let last = last?;
+    let mut plan = vec![order[last]];
+    let (mut folder, mut sum) = (last, best - sizes[last]);
+    for layer in layers.iter().rev() {
+      if sum == 0 {
+        break;
+      }
+      folder = layer.added_by(folder, sum);
+      sum -= sizes[folder];
+      plan.push(order[folder]);
+    }
+    plan.sort_by_cached_key(|&id| self.full_path(id));
+    Some(plan)
+  }
+
+  /// Folders in preorder, with the index just past each one's subtree.
+  fn folder_preorder(&self) -> (Vec<NodeId>, Vec<usize>) {
+    let mut order = Vec::new();
+    let mut subtree_end = Vec::new();
+    self.push_preorder(Self::ROOT, &mut order, &mut subtree_end);
+    (order, subtree_end)
+  }
+
+  fn push_preorder(&self, id: NodeId, order: &mut Vec<NodeId>, subtree_end: &mut Vec<usize>) {
+    let idx = order.len();
+    order.push(id);
+    subtree_end.push(idx + 1);
+    for child in self.sorted_children(id, SortBy::Name) {
+      if self.is_folder(child) {
+        self.push_preorder(child, order, subtree_end);
+      }
+    }
+    subtree_end[idx] = order.len();
+  }
+}
+
+impl Default for FileSystem {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortBy {
+  Name,
+  Size,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+  File,
+  Folder,
+}
+
+/// Filters over the tree, e.g. `Query::new().files().name_matches("*.log").min_depth(4)`.
+/// Sizes and depths are inclusive bounds; the root sits at depth 0.
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+  kind: Option<Kind>,
+  min_size: Option<usize>,
+  max_size: Option<usize>,
+  min_depth: Option<usize>,
+  max_depth: Option<usize>,
+  glob: Option<String>,
+  sort: Option<SortBy>,
+  limit: Option<usize>,
+}
+
+impl Query {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn files(mut self) -> Self {
+    self.kind = Some(Kind::File);
+    self
+  }
+
+  pub fn folders(mut self) -> Self {
+    self.kind = Some(Kind::Folder);
+    self
+  }
+
+  pub fn min_size(mut self, size: usize) -> Self {
+    self.min_size = Some(size);
+    self
   }
 
-  fn get_folder(&mut self, name: &str) -> Rc<RefCell<Self>> {
-    match self {
-      Self::File(_) => panic!("Cannot retrieve a folder from file"),
-      Self::Folder(children) => (*children)
-          .entry(name.to_owned())
-          .or_insert_with(Self::new_folder)
-          .clone(),
+  pub fn max_size(mut self, size: usize) -> Self {
+    self.max_size = Some(size);
+    self
+  }
+
+  pub fn min_depth(mut self, depth: usize) -> Self {
+    self.min_depth = Some(depth);
+    self
+  }
+
+  pub fn max_depth(mut self, depth: usize) -> Self {
+    self.max_depth = Some(depth);
+    self
+  }
+
+  pub fn name_matches(mut self, glob: &str) -> Self {
+    self.glob = Some(glob.to_owned());
+    self
+  }
+
+  pub fn sort_by(mut self, sort: SortBy) -> Self {
+    self.sort = Some(sort);
+    self
+  }
+
+  pub fn limit(mut self, limit: usize) -> Self {
+    self.limit = Some(limit);
+    self
+  }
+
+  fn matches(&self, fs: &FileSystem, id: NodeId) -> bool {
+    let size = fs.get_size(id);
+    let depth = fs.depth(id);
+    let kind = if fs.is_folder(id) { Kind::Folder } else { Kind::File };
+    self.kind.is_none_or(|k| k == kind)
+        && self.min_size.is_none_or(|min| size >= min)
+        && self.max_size.is_none_or(|max| size <= max)
+        && self.min_depth.is_none_or(|min| depth >= min)
+        && self.max_depth.is_none_or(|max| depth <= max)
+        && self.glob.as_ref().is_none_or(|glob| glob_match(glob, fs.name(id)))
+  }
+}
+
+/// Matches `name` against a shell glob supporting `*` and `?`.
+pub fn glob_match(glob: &str, name: &str) -> bool {
+  let glob: Vec<char> = glob.chars().collect();
+  let name: Vec<char> = name.chars().collect();
+  let (mut g, mut n) = (0, 0);
+  let mut backtrack: Option<(usize, usize)> = None;
+  while n < name.len() {
+    if g < glob.len() && (glob[g] == '?' || glob[g] == name[n]) {
+      g += 1;
+      n += 1;
+    } else if g < glob.len() && glob[g] == '*' {
+      backtrack = Some((g, n));
+      g += 1;
+    } else if let Some((star, matched)) = backtrack {
+      g = star + 1;
+      n = matched + 1;
+      backtrack = Some((star, matched + 1));
+    } else {
+      return false;
     }
   }
+  glob[g..].iter().all(|&c| c == '*')
+}
+
+/// Formats a byte count with binary units, rounding up like `du -h`.
+pub fn human_size(size: usize) -> String {
+  const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+  if size < 1024 {
+    return size.to_string();
+  }
+  let mut value = size as f64;
+  let mut unit = 0;
+  value /= 1024.0;
+  while value >= 1024.0 && unit + 1 < UNITS.len() {
+    value /= 1024.0;
+    unit += 1;
+  }
+  if value < 10.0 {
+    format!("{:.1}{}", (value * 10.0).ceil() / 10.0, UNITS[unit])
+  } else {
+    format!("{}{}", value.ceil(), UNITS[unit])
+  }
+}
 
-  fn get_size(&mut self) -> (usize, Vec<usize>) {
-    match self {
-      Path::File(size) => (*size, vec![]),
-      Path::Folder(children) => {
-        let mut total_size = 0;
-        let mut total_size_list = Vec::new();
+/// Bits newly set in one word of a layer by deleting `folder`. Each bit is only ever set once,
+/// so these double as the log for rolling a layer back to when a folder was reached.
+struct Birth {
+  word: u32,
+  folder: u32,
+  bits: u64,
+}
+
+/// Sums below the planner's target, one bit each, as reachable so far in a preorder walk. A sum
+/// reachable at one index stays reachable at every later one by deleting nothing more, so one
+/// layer serves the whole walk.
+struct Layer {
+  bits: Vec<u64>,
+  births: Vec<Birth>,
+  // births when each folder was reached
+  marks: Vec<usize>,
+}
 
-        for child in children.values() {
-          let mut child = child.deref().borrow_mut();
-          let (child_total_size, child_total_size_list) = child.get_size();
-          total_size += child_total_size;
-          total_size_list.extend(child_total_size_list);
+impl Layer {
+  /// A layer holding only the empty sum.
+  fn new(limit: usize) -> Self {
+    let mut bits = vec![0; limit.div_ceil(64)];
+    bits[0] = 1;
+    Layer { bits, births: Vec::new(), marks: Vec::new() }
+  }
+
+  /// Walks the folders in preorder, adding each deleted folder on top of the sums `from` held
+  /// when it was reached, or on top of this layer's own when `from` is `None`. Stops at the first
+  /// folder `done` accepts when it is reached, returning its index.
+  fn walk(
+    limit: usize,
+    sizes: &[usize],
+    subtree_end: &[usize],
+    from: Option<&Layer>,
+    mut done: impl FnMut(&Layer, usize) -> bool,
+  ) -> (Layer, Option<usize>) {
+    let mut layer = Layer::new(limit);
+    let mut open = Vec::new();
+    for idx in 0..sizes.len() {
+      layer.marks.push(layer.births.len());
+      if done(&layer, idx) {
+        return (layer, Some(idx));
+      }
+      open.push(idx);
+      while let Some(folder) = open.pop_if(|folder| subtree_end[*folder] == idx + 1) {
+        let words = limit.saturating_sub(sizes[folder]).div_ceil(64);
+        if words > 0 {
+          let reached = from.unwrap_or(&layer).rolled_back(folder, words);
+          layer.add_shifted(&reached, sizes[folder], limit, folder);
         }
+      }
+    }
+    (layer, None)
+  }
+
+  fn contains(&self, sum: usize) -> bool {
+    self.bits.get(sum / 64).is_some_and(|word| word >> (sum % 64) & 1 == 1)
+  }
+
+  /// The smallest sum of at least `sum`.
+  fn first_from(&self, sum: usize) -> Option<usize> {
+    let first = self.bits.get(sum / 64)? & (u64::MAX << (sum % 64));
+    if first != 0 {
+      return Some(sum / 64 * 64 + first.trailing_zeros() as usize);
+    }
+    (sum / 64 + 1..self.bits.len())
+        .find(|&word| self.bits[word] != 0)
+        .map(|word| word * 64 + self.bits[word].trailing_zeros() as usize)
+  }
 
-        total_size_list.push(total_size);
-        (total_size, total_size_list)
+  /// The folder whose deletion first reached `sum`, which happened before `folder` was reached.
+  fn added_by(&self, folder: usize, sum: usize) -> usize {
+    self.births[..self.marks[folder]]
+        .iter()
+        .find(|birth| birth.word as usize == sum / 64 && birth.bits >> (sum % 64) & 1 == 1)
+        .map(|birth| birth.folder as usize)
+        .expect("a sum is only used once it has been reached")
+  }
+
+  /// The words below `words` as they were when `folder` was reached.
+  fn rolled_back(&self, folder: usize, words: usize) -> Vec<u64> {
+    let mut bits = self.bits[..words].to_vec();
+    for birth in &self.births[self.marks[folder]..] {
+      if let Some(word) = bits.get_mut(birth.word as usize) {
+        *word &= !birth.bits;
+      }
+    }
+    bits
+  }
+
+  /// Sets every sum of `from` moved up by `size`, as long as it stays below `limit`.
+  fn add_shifted(&mut self, from: &[u64], size: usize, limit: usize, folder: usize) {
+    let (offset, shift) = (size / 64, (size % 64) as u32);
+    let mut carry = 0;
+    for word in offset..self.bits.len().min(offset + from.len() + 1) {
+      let source = from.get(word - offset).copied().unwrap_or(0);
+      let moved = source << shift | carry;
+      carry = source.checked_shr(64 - shift).unwrap_or(0);
+      if moved == 0 {
+        continue;
+      }
+      let mut new = moved & !self.bits[word];
+      if (word + 1) * 64 > limit {
+        new &= (1 << (limit % 64)) - 1;
+      }
+      if new != 0 {
+        self.bits[word] |= new;
+        self.births.push(Birth { word: word as u32, folder: folder as u32, bits: new });
       }
     }
   }
 }
 
+pub const DISK_SIZE: usize = 70_000_000;
+pub const REQUIRED_SPACE: usize = 30_000_000;
+
 #[aoc_generator(day7)]
-fn parse_input(input: &str) -> Rc<RefCell<Path>> {
-  let root = Path::new_folder();
-  let mut cwd = vec![root.clone()];
-  let cd_re = Regex::new(r"^\$ cd (?<dir>/|\.\.|\w+)$").unwrap();
-  let ls_file_re = Regex::new(r"^(?<file_size>\d+) (?<file_name>\w+\.*\w*)$").unwrap();
+fn parse_input(input: &str) -> FileSystem {
+  let mut fs = FileSystem::new();
+  let mut cwd = FileSystem::ROOT;
+  let cd_re = Regex::new(r"^\$ cd (?<dir>.+)$").unwrap();
+  let ls_dir_re = Regex::new(r"^dir (?<dir_name>.+)$").unwrap();
+  let ls_file_re = Regex::new(r"^(?<file_size>\d+) (?<file_name>.+)$").unwrap();
 
   for line in input.lines() {
     let cd_cap = cd_re.captures(line);
+    let ls_dir_cap = ls_dir_re.captures(line);
     let ls_file_cap = ls_file_re.captures(line);
 
     if let Some(cap) = cd_cap {
       match &cap["dir"] {
         "/" => {
-          cwd = vec![root.clone()];
+          cwd = FileSystem::ROOT;
         }
         ".." => {
-          cwd.pop();
+          cwd = fs.parent(cwd).unwrap_or(FileSystem::ROOT);
         }
         dir_name => {
-          let folder = cwd.last().unwrap().as_ref().borrow_mut().get_folder(dir_name);
-          cwd.push(folder);
+          cwd = fs.get_folder(cwd, dir_name);
         }
       }
       continue;
     }
 
+    // dir -> make sure empty folders exist too
+    if let Some(cap) = ls_dir_cap {
+      fs.get_folder(cwd, &cap["dir_name"]);
+      continue;
+    }
+
     // number -> push new file
     if let Some(cap) = ls_file_cap {
-      let size: usize = (&cap["file_size"]).parse().unwrap();
-      cwd.last()
-          .unwrap()
-          .as_ref()
-          .borrow_mut()
-          .add_file(
-            &cap["file_name"],
-            size
-          );
+      let size: usize = cap["file_size"].parse().unwrap();
+      fs.add_file(cwd, &cap["file_name"], size);
     }
   }
 
-  root
+  fs
 }
 
 #[aoc(day7, part1)]
-fn sum_less_100k(input: &Rc<RefCell<Path>>) -> usize {
-  let (_, size_list) = (*input).deref().borrow_mut().get_size();
-  return size_list.iter().filter(|x| **x <= 100_000).sum();
+fn sum_less_100k(input: &FileSystem) -> usize {
+  input.query(&Query::new().folders().max_size(100_000))
+      .iter()
+      .map(|&id| input.get_size(id))
+      .sum()
 }
 
 #[aoc(day7, part2)]
-fn free(input: &Rc<RefCell<Path>>) -> usize {
-  let (total_size, size_list) = (*input).deref().borrow_mut().get_size();
-  let must_free = total_size - 40_000_000;
-  return size_list.iter().filter(|x| **x >= must_free).min().unwrap().to_owned();
+fn free(input: &FileSystem) -> usize {
+  let folder = input.smallest_deletion(DISK_SIZE, REQUIRED_SPACE).unwrap();
+  input.get_size(folder)
+}
+
+#[cfg(test)]
+mod test {
+  use std::fs;
+  use std::time::{Duration, Instant};
+
+  use crate::day7::{free, glob_match, human_size, parse_input, sum_less_100k, FileSystem, Query, SortBy};
+
+  const EXAMPLE: &str = "$ cd /
+$ ls
+dir a
+14848514 b.txt
+8504156 c.dat
+dir d
+$ cd a
+$ ls
+dir e
+29116 f
+2557 g
+62596 h.lst
+$ cd e
+$ ls
+584 i
+$ cd ..
+$ cd ..
+$ cd d
+$ ls
+4060174 j
+8033020 d.log
+5626152 d.ext
+7214296 k";
+
+  #[test]
+  fn test_cached_sizes() {
+    let fs = parse_input(EXAMPLE);
+    let a = fs.child(FileSystem::ROOT, "a").unwrap();
+    let e = fs.child(a, "e").unwrap();
+
+    assert_eq!(48381165, fs.get_size(FileSystem::ROOT));
+    assert_eq!(94853, fs.get_size(a));
+    assert_eq!(584, fs.get_size(e));
+    assert_eq!(Some(a), fs.parent(e));
+  }
+
+  #[test]
+  fn test_relisted_file_is_not_double_counted() {
+    let mut fs = FileSystem::new();
+    fs.add_file(FileSystem::ROOT, "a", 10);
+    fs.add_file(FileSystem::ROOT, "a", 15);
+
+    assert_eq!(15, fs.get_size(FileSystem::ROOT));
+  }
+
+  #[test]
+  fn test_render_tree() {
+    let fs = parse_input(EXAMPLE);
+    let expected = "[48381165]  /
+├── [94853]  a
+│   ├── [584]  e
+│   │   └── [584]  i
+│   ├── [29116]  f
+│   ├── [2557]  g
+│   └── [62596]  h.lst
+├── [14848514]  b.txt
+├── [8504156]  c.dat
+└── [24933642]  d
+    ├── [5626152]  d.ext
+    ├── [8033020]  d.log
+    ├── [4060174]  j
+    └── [7214296]  k
+";
+
+    assert_eq!(expected, fs.render_tree(SortBy::Name));
+  }
+
+  #[test]
+  fn test_du_entries() {
+    let fs = parse_input(EXAMPLE);
+
+    assert_eq!(
+      vec![("/".to_owned(), 48381165), ("/d".to_owned(), 24933642), ("/a".to_owned(), 94853)],
+      fs.du_entries(1, SortBy::Size)
+    );
+    assert_eq!(
+      vec![("/".to_owned(), 48381165), ("/a".to_owned(), 94853), ("/a/e".to_owned(), 584), ("/d".to_owned(), 24933642)],
+      fs.du_entries(2, SortBy::Name)
+    );
+    assert_eq!("47M\t/\n24M\t/d\n93K\t/a\n", fs.render_du(1, SortBy::Size));
+  }
+
+  #[test]
+  fn test_human_size() {
+    assert_eq!("584", human_size(584));
+    assert_eq!("1.0K", human_size(1024));
+    assert_eq!("1.1K", human_size(1025));
+    assert_eq!("93K", human_size(94853));
+  }
+
+  #[test]
+  fn test_space_to_free() {
+    let fs = parse_input(EXAMPLE);
+
+    assert_eq!(8381165, fs.space_to_free(70_000_000, 30_000_000));
+    assert_eq!(0, fs.space_to_free(100_000_000, 30_000_000));
+  }
+
+  #[test]
+  fn test_plan_deletion_sample() {
+    let fs = parse_input(EXAMPLE);
+    let d = fs.child(FileSystem::ROOT, "d").unwrap();
+
+    assert_eq!(Some(vec![d]), fs.plan_deletion(70_000_000, 30_000_000));
+    assert_eq!(Some(vec![]), fs.plan_deletion(100_000_000, 30_000_000));
+    assert_eq!(Some(vec![FileSystem::ROOT]), fs.plan_deletion(40_000_000, 30_000_000));
+    assert_eq!(None, fs.plan_deletion(10_000_000, 30_000_000));
+  }
+
+  #[test]
+  fn test_plan_deletion_prefers_siblings() {
+    let fs = parse_input("$ cd /
+$ cd x
+6 a
+$ cd ..
+$ cd y
+5 b
+$ cd ..
+$ cd z
+30 c
+$ cd w
+1 d");
+    let x = fs.child(FileSystem::ROOT, "x").unwrap();
+    let y = fs.child(FileSystem::ROOT, "y").unwrap();
+    let z = fs.child(FileSystem::ROOT, "z").unwrap();
+    let w = fs.child(z, "w").unwrap();
+
+    assert_eq!(Some(z), fs.smallest_deletion(46, 15));
+    assert_eq!(Some(vec![x, y]), fs.plan_deletion(46, 15));
+    assert_eq!(Some(vec![x, y, w]), fs.plan_deletion(45, 15));
+  }
+
+  // Folders nested under random earlier ones, each holding one file of a pseudo-random size.
+  fn random_tree(folders: usize, max_file: usize, mut seed: usize) -> FileSystem {
+    let mut next = |modulo: usize| {
+      seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+      (seed >> 33) % modulo
+    };
+    let mut fs = FileSystem::new();
+    let mut ids = vec![FileSystem::ROOT];
+    for idx in 0..folders {
+      let folder = fs.get_folder(ids[next(ids.len())], &format!("f{}", idx));
+      fs.add_file(folder, "data", 1 + next(max_file));
+      ids.push(folder);
+    }
+    fs
+  }
+
+  fn is_nested(fs: &FileSystem, plan: &[usize]) -> bool {
+    plan.iter().any(|&id| {
+      let mut ancestor = fs.parent(id);
+      while let Some(parent) = ancestor {
+        if plan.contains(&parent) {
+          return true;
+        }
+        ancestor = fs.parent(parent);
+      }
+      false
+    })
+  }
+
+  #[test]
+  fn test_plan_deletion_matches_exhaustive_search() {
+    for seed in 0..20 {
+      let fs = random_tree(11, 40, seed);
+      let folders: Vec<usize> = fs.folders().collect();
+      let must_free = fs.get_size(FileSystem::ROOT) * (seed % 5 + 1) / 6;
+      let disk_size = fs.get_size(FileSystem::ROOT) + 100 - must_free;
+
+      let best = (0..1usize << folders.len())
+          .map(|mask| folders.iter().enumerate().filter(|(bit, _)| mask >> bit & 1 == 1).map(|(_, &id)| id).collect::<Vec<_>>())
+          .filter(|plan| !is_nested(&fs, plan))
+          .map(|plan| (plan.iter().map(|&id| fs.get_size(id)).sum::<usize>(), plan.len()))
+          .filter(|&(freed, _)| freed >= must_free)
+          .min();
+      let plan = fs.plan_deletion(disk_size, 100).unwrap();
+
+      assert!(!is_nested(&fs, &plan));
+      assert_eq!(best, Some((plan.iter().map(|&id| fs.get_size(id)).sum(), plan.len())));
+    }
+  }
+
+  #[test]
+  fn test_plan_deletion_puzzle_scale() {
+    // as big as a puzzle input, with millions of sums below the target
+    let fs = random_tree(200, 400_000, 3);
+    let must_free = 3_000_000;
+    let disk_size = fs.get_size(FileSystem::ROOT) + 30_000_000 - must_free;
+
+    let started = Instant::now();
+    let plan = fs.plan_deletion(disk_size, 30_000_000).unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(10), "took {:?}", started.elapsed());
+    assert!(!is_nested(&fs, &plan));
+    assert_eq!(must_free, plan.iter().map(|&id| fs.get_size(id)).sum());
+  }
+
+  #[test]
+  fn test_plan_deletion_large_tree() {
+    let fs = random_tree(400, 60, 7);
+    let must_free = fs.get_size(FileSystem::ROOT) / 4;
+    let disk_size = fs.get_size(FileSystem::ROOT) + 1000 - must_free;
+
+    let plan = fs.plan_deletion(disk_size, 1000).unwrap();
+    let freed: usize = plan.iter().map(|&id| fs.get_size(id)).sum();
+
+    assert!(!is_nested(&fs, &plan));
+    // with this many small folders some of them add up to exactly the target
+    assert_eq!(must_free, freed);
+    assert!(freed <= fs.get_size(fs.smallest_deletion(disk_size, 1000).unwrap()));
+  }
+
+  #[test]
+  fn test_transcript_round_trip() {
+    let fs = parse_input(EXAMPLE);
+    let transcript = fs.to_transcript();
+
+    assert!(transcript.starts_with("$ cd /\n$ ls\ndir a\n14848514 b.txt\n8504156 c.dat\ndir d\n$ cd a\n"));
+    assert_eq!(fs.render_tree(SortBy::Name), parse_input(&transcript).render_tree(SortBy::Name));
+  }
+
+  #[test]
+  fn test_from_dir() {
+    let dir = std::env::temp_dir().join(format!("aoc22-day7-{}", std::process::id()));
+    fs::create_dir_all(dir.join("a b").join("empty")).unwrap();
+    fs::write(dir.join("a b").join("f.log"), [0u8; 12]).unwrap();
+    fs::write(dir.join("g"), [0u8; 30]).unwrap();
+
+    let tree = FileSystem::from_dir(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    let tree = tree.unwrap();
+    let a = tree.child(FileSystem::ROOT, "a b").unwrap();
+
+    assert_eq!(42, tree.get_size(FileSystem::ROOT));
+    assert_eq!(12, tree.get_size(a));
+    assert!(tree.child(a, "empty").is_some());
+    assert_eq!(tree.render_tree(SortBy::Name), parse_input(&tree.to_transcript()).render_tree(SortBy::Name));
+  }
+
+  #[test]
+  fn test_glob_match() {
+    assert!(glob_match("*.log", "d.log"));
+    assert!(glob_match("*", ""));
+    assert!(glob_match("?.l*t", "h.lst"));
+    assert!(glob_match("a*b*c", "aXbYbc"));
+    assert!(!glob_match("*.log", "d.ext"));
+    assert!(!glob_match("?", "ab"));
+  }
+
+  #[test]
+  fn test_query() {
+    let fs = parse_input(EXAMPLE);
+    let names = |ids: Vec<usize>| -> Vec<String> { ids.iter().map(|&id| fs.full_path(id)).collect() };
+
+    assert_eq!(
+      vec!["/a", "/a/e"],
+      names(fs.query(&Query::new().folders().max_size(100_000).sort_by(SortBy::Name)))
+    );
+    assert_eq!(
+      vec!["/b.txt", "/c.dat", "/d/d.log"],
+      names(fs.query(&Query::new().files().sort_by(SortBy::Size).limit(3)))
+    );
+    assert_eq!(
+      vec!["/d/d.ext", "/d/d.log"],
+      names(fs.query(&Query::new().files().name_matches("d.*").min_depth(2).sort_by(SortBy::Name)))
+    );
+    assert_eq!(
+      vec!["/a/e/i"],
+      names(fs.query(&Query::new().files().min_depth(3)))
+    );
+  }
+
+  #[test]
+  fn sample_test_part1() {
+    assert_eq!(95437, sum_less_100k(&parse_input(EXAMPLE)));
+  }
+
+  #[test]
+  fn sample_test_part2() {
+    assert_eq!(24933642, free(&parse_input(EXAMPLE)));
+  }
 }
@@ -1,172 +1,311 @@
-use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
 use std::str::FromStr;
 
+use crate::grid::{Grid, DIRS8};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn step(self) -> (isize, isize) {
+        match self {
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
-struct Tree {
-    visible: bool,
-    height: u8,
-    scenic_scores: HashMap<u8, usize>,
+pub struct Tree {
+    height: u32,
+    viewing_distances: [usize; 4],
+    visible_from: [bool; 4],
 }
 
 impl Tree {
-    fn new(height: u8) -> Self {
+    fn new(height: u32) -> Self {
         Tree {
-            visible: false,
             height,
-            scenic_scores: HashMap::new(),
+            viewing_distances: [0; 4],
+            visible_from: [false; 4],
         }
     }
 
-    fn update_scenic_score(&mut self, cardinality: u8, vectors: Vec<&Tree>)
-    {
-        self.scenic_scores.insert(cardinality, 0);
-        for tree in vectors {
-            *self.scenic_scores.entry(cardinality).or_insert(0) += 1;
-            if tree.height >= self.height {
-                return;
-            }
-        }
-        self.visible = true;
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Number of trees seen looking `direction` before the view is blocked or the edge is reached.
+    pub fn viewing_distance(&self, direction: Direction) -> usize {
+        self.viewing_distances[direction.index()]
     }
 
-    fn get_scenic_score(&self) -> usize {
-        let mut product = 1;
-        self.scenic_scores.values().for_each(|&score| product *= score);
-        product
+    /// Whether the tree can be seen from outside the forest looking in against `direction`.
+    pub fn is_visible_from(&self, direction: Direction) -> bool {
+        self.visible_from[direction.index()]
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible_from.iter().any(|&visible| visible)
+    }
+
+    pub fn get_scenic_score(&self) -> usize {
+        self.viewing_distances.iter().product()
     }
 }
 
 #[derive(Debug)]
-struct ForestMap {
-    trees: Vec<Tree>,
-    num_cols: usize,
-    num_rows: usize,
+pub struct ForestMap {
+    trees: Grid<Tree>,
 }
 
 impl FromStr for ForestMap {
-    type Err = ();
+    type Err = String;
 
+    /// Reads either the puzzle's one-digit-per-tree map or rows of numbers separated by spaces or
+    /// commas, so heights can go past 9. Each line's format is told apart after trimming trailing
+    /// whitespace, and a map mixing the two is rejected, as are ragged rows and bad cells.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut map = ForestMap::new();
-        for line in s.lines() {
-            for byte in line.bytes() {
-                map.push(Tree::new(byte - 48));
-            }
-            map.push_row();
+        let lines: Vec<&str> = s.lines().map(str::trim_end).collect();
+        let is_separator = |c: char| c == ',' || c.is_whitespace();
+        // a lone height reads the same either way
+        let separated = |line: &str| line.contains(is_separator);
+        let digits = |line: &str| !separated(line) && line.chars().count() > 1;
+        if let (Some(sep), Some(dig)) = (
+            lines.iter().position(|line| separated(line)),
+            lines.iter().position(|line| digits(line)),
+        ) {
+            return Err(format!("Row {} is separated numbers but row {} is digits", sep, dig));
         }
-        Ok(map)
+
+        let trees = if lines.iter().any(|line| separated(line)) {
+            let mut rows = Vec::new();
+            for (y, line) in lines.iter().enumerate() {
+                let row = line
+                    .split(is_separator)
+                    .filter(|cell| !cell.is_empty())
+                    .enumerate()
+                    .map(|(x, cell)| {
+                        cell.parse()
+                            .map(Tree::new)
+                            .map_err(|_| format!("({}, {}): '{}' is not a height", x, y, cell))
+                    })
+                    .collect::<Result<Vec<Tree>, String>>()?;
+                rows.push(row);
+            }
+            Grid::from_rows(rows)?
+        } else {
+            Grid::parse(&lines.join("\n"), |c| {
+                c.to_digit(10)
+                    .map(Tree::new)
+                    .ok_or(format!("'{}' is not a digit", c))
+            })?
+        };
+        Ok(ForestMap { trees })
     }
 }
 
 impl ForestMap {
-    fn new() -> Self {
-        ForestMap {
-            trees: Vec::new(),
-            num_cols: 0,
-            num_rows: 0,
+    // Walks one line of trees keeping a stack of the ones still tall enough to block the view,
+    // so each tree learns how far it sees back towards the start of the line in O(1) amortised.
+    fn sweep(&mut self, line: &[usize], direction: Direction) {
+        let mut stack: Vec<usize> = Vec::new();
+        for (pos, &idx) in line.iter().enumerate() {
+            let height = self.trees.cells()[idx].height;
+            while let Some(&top) = stack.last() {
+                if self.trees.cells()[line[top]].height >= height {
+                    break;
+                }
+                stack.pop();
+            }
+            let tree = &mut self.trees.cells_mut()[idx];
+            let distance = match stack.last() {
+                Some(&top) => pos - top,
+                None => {
+                    tree.visible_from[direction.index()] = true;
+                    pos
+                }
+            };
+            tree.viewing_distances[direction.index()] = distance;
+            stack.push(pos);
         }
     }
 
-    fn push_row(&mut self) {
-        if self.num_cols == 0 {
-            self.num_cols = self.trees.len()
+    fn set_scenic_scores(&mut self)
+    {
+        for col in 0..self.trees.width() {
+            let mut line: Vec<usize> = (0..self.trees.height()).map(|row| self.trees.index_of(col, row)).collect();
+            self.sweep(&line, Direction::Up);
+            line.reverse();
+            self.sweep(&line, Direction::Down);
+        }
+        for row in 0..self.trees.height() {
+            let mut line: Vec<usize> = (0..self.trees.width()).map(|col| self.trees.index_of(col, row)).collect();
+            self.sweep(&line, Direction::Left);
+            line.reverse();
+            self.sweep(&line, Direction::Right);
         }
-        self.num_rows += 1;
     }
 
-    fn push(&mut self, tree: Tree) {
-        if self.num_cols == 0 { 0 } else { self.trees.len() / self.num_cols };
-        self.trees.push(tree);
+    pub fn tree(&self, x: usize, y: usize) -> Option<&Tree> {
+        self.trees.get(x, y)
     }
 
-    fn get_up_indices(&self, location: (usize, usize)) -> Vec<usize> {
-        (0..(location.0 + location.1 * self.num_cols)).rev().filter(move |x| {
-            *x % self.num_cols == location.0
-        }).collect()
+    /// Coordinates and score of the tree with the highest scenic score, the first in reading
+    /// order on ties.
+    pub fn best_scenic_tree(&self) -> Option<((usize, usize), usize)> {
+        self.trees
+            .iter()
+            .map(|(pos, tree)| (pos, tree.get_scenic_score()))
+            .reduce(|best, cur| if cur.1 > best.1 { cur } else { best })
     }
 
-    fn get_down_indices(&self, location: (usize, usize)) -> Vec<usize> {
-        ((location.0 + location.1 * self.num_cols + 1)..self.trees.len()).filter(move |x| {
-            *x % self.num_cols == location.0
-        }).collect()
+    fn _print_visible(&self) {
+        print!("{}", self.trees.render(|tree| if tree.is_visible() { 'X' } else { ' ' }));
     }
 
-    fn get_right_indices(&self, location: (usize, usize)) -> Vec<usize> {
-        ((location.0 + location.1 * self.num_cols + 1)..self.num_cols * (location.1 + 1)).collect()
+    /// Trees seen by an observer standing at `from` with eyes at `eye_height`, looking along
+    /// `direction` (so `(2, 1)` looks out at a shallow diagonal). Every tree whose cell the sight
+    /// line crosses is checked, and one is seen when its top rises above the line over every
+    /// nearer tree.
+    pub fn visible_along(&self, from: (usize, usize), eye_height: u32, direction: (isize, isize)) -> Vec<(usize, usize)> {
+        let (dx, dy) = (direction.0 as i64, direction.1 as i64);
+        let mut seen = Vec::new();
+        // steepest slope so far as rise / run, with the run measured to where the tree's centre
+        // projects onto the line, in units of |direction|²
+        let mut steepest: Option<(i64, i64)> = None;
+        for pos in self.trees.trace(from, direction) {
+            let rise = i64::from(self.trees[pos].height) - i64::from(eye_height);
+            let run = (pos.0 as i64 - from.0 as i64) * dx + (pos.1 as i64 - from.1 as i64) * dy;
+            let above = match steepest {
+                None => true,
+                Some((max_rise, max_run)) => rise * max_run > max_rise * run,
+            };
+            if above {
+                seen.push(pos);
+                steepest = Some((rise, run));
+            }
+        }
+        seen
     }
 
-    fn get_left_indices(&self, location: (usize, usize)) -> Vec<usize> {
-        (self.num_cols * (location.1)..(location.0 + location.1 * self.num_cols)).rev().collect()
+    /// Every tree seen from `from` along the eight compass directions, in reading order.
+    pub fn visible_from(&self, from: (usize, usize), eye_height: u32) -> Vec<(usize, usize)> {
+        let mut seen: Vec<(usize, usize)> = DIRS8.iter()
+            .flat_map(|&step| self.visible_along(from, eye_height, step))
+            .collect();
+        seen.sort_by_key(|&(x, y)| (y, x));
+        seen
     }
 
-    fn set_scenic_scores(&mut self)
-    {
-        for col in 0..self.num_cols {
-            for row in 0..self.num_rows {
-                let immut_trees: Vec<Tree> = self.trees.clone();
-                let up_trees: Vec<&Tree> = immut_trees.iter()
-                    .enumerate()
-                    .filter(|(index, _)| self.get_up_indices((col, row)).contains(index))
-                    .map(|(_, tree)| tree)
-                    .collect();
-                let down_trees = immut_trees.iter()
-                    .enumerate()
-                    .filter(|(index, _)| self.get_down_indices((col, row)).contains(index))
-                    .map(|(_, tree)| tree)
-                    .collect();
-                let right_trees = immut_trees.iter()
-                    .enumerate()
-                    .filter(|(index, _)| self.get_right_indices((col, row)).contains(index))
-                    .map(|(_, tree)| tree)
-                    .collect();
-                let left_trees: Vec<&Tree> = immut_trees.iter()
-                    .enumerate()
-                    .filter(|(index, _)| self.get_left_indices((col, row)).contains(index))
-                    .map(|(_, tree)| tree)
-                    .collect();
-
-                self.trees.get_mut(col + row * self.num_cols)
-                    .unwrap()
-                    .update_scenic_score(
-                        0,
-                        up_trees.into_iter().rev().collect(),
-                    );
-
-                self.trees.get_mut(col + row * self.num_cols)
-                    .unwrap()
-                    .update_scenic_score(
-                        1,
-                        down_trees,
-                    );
-
-                self.trees.get_mut(col + row * self.num_cols)
-                    .unwrap()
-                    .update_scenic_score(
-                        2,
-                        right_trees,
-                    );
-
-                self.trees.get_mut(col + row * self.num_cols)
-                    .unwrap()
-                    .update_scenic_score(
-                        3,
-                        left_trees.into_iter().rev().collect(),
-                    );
+    /// Intensity of every tree from 0 to 255 for the given shading.
+    pub fn heatmap(&self, shading: Shading) -> Grid<u8> {
+        let scale = |value: usize, max: usize| (value * 255).checked_div(max).unwrap_or(0) as u8;
+        match shading {
+            Shading::Height => {
+                let max = self.trees.cells().iter().map(|tree| tree.height as usize).max().unwrap_or(0);
+                self.trees.map(|tree| scale(tree.height as usize, max))
+            }
+            Shading::Visible => self.trees.map(|tree| if tree.is_visible() { 255 } else { 0 }),
+            Shading::Scenic => {
+                let max = self.best_scenic_tree().map_or(0, |(_, score)| score);
+                self.trees.map(|tree| scale(tree.get_scenic_score(), max))
             }
         }
     }
 
-    fn _print_visible(&self) {
-        let vec_idx = |col: usize, row: usize| col + row * self.num_cols;
-
-        for col in 0..self.num_cols {
-            for row in 0..self.num_rows {
-                let visible = if self.trees.get(vec_idx(col, row)).unwrap().visible { "X" } else {
-                    " "
-                };
-                print!("{}", visible);
-            }
-            println!();
+    pub fn to_pgm(&self, shading: Shading) -> String {
+        let heatmap = self.heatmap(shading);
+        let mut out = format!("P2\n{} {}\n255\n", heatmap.width(), heatmap.height());
+        for row in heatmap.rows() {
+            let values: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+            out.push_str(&values.join(" "));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn to_ppm(&self, shading: Shading) -> String {
+        let heatmap = self.heatmap(shading);
+        let mut out = format!("P3\n{} {}\n255\n", heatmap.width(), heatmap.height());
+        for row in heatmap.rows() {
+            let values: Vec<String> = row.iter()
+                .map(|&value| {
+                    let (r, g, b) = shading.colour(value);
+                    format!("{} {} {}", r, g, b)
+                })
+                .collect();
+            out.push_str(&values.join("  "));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn to_svg(&self, shading: Shading, cell_size: usize) -> String {
+        let heatmap = self.heatmap(shading);
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n",
+            heatmap.width() * cell_size,
+            heatmap.height() * cell_size
+        );
+        for ((x, y), &value) in heatmap.iter() {
+            let (r, g, b) = shading.colour(value);
+            out.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"rgb({},{},{})\"/>\n",
+                x * cell_size,
+                y * cell_size,
+                cell_size,
+                cell_size,
+                r,
+                g,
+                b
+            ));
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+
+    /// Writes the heatmap to `path`, picking PGM, PPM or SVG from the file extension.
+    pub fn write_image(&self, path: &Path, shading: Shading) -> io::Result<()> {
+        let contents = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("pgm") => self.to_pgm(shading),
+            Some("ppm") => self.to_ppm(shading),
+            Some("svg") => self.to_svg(shading, 10),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "Expected a .pgm, .ppm or .svg path")),
+        };
+        fs::write(path, contents)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shading {
+    Height,
+    Visible,
+    Scenic,
+}
+
+impl Shading {
+    fn colour(self, value: u8) -> (u8, u8, u8) {
+        match self {
+            Shading::Height => (value / 4, value, value / 4),
+            Shading::Visible if value > 0 => (34, 139, 34),
+            Shading::Visible => (40, 40, 40),
+            Shading::Scenic => (value.saturating_mul(2), value.saturating_sub(128).saturating_mul(2), 0),
         }
     }
 }
@@ -180,22 +319,18 @@ fn create_visibility_map(input: &str) -> ForestMap {
 
 #[aoc(day8, part1)]
 fn count_visible(input: &ForestMap) -> usize {
-    input.trees.iter().map(|tree| {
-        if tree.visible { 1 } else { 0 }
+    input.trees.cells().iter().map(|tree| {
+        if tree.is_visible() { 1 } else { 0 }
     }).sum()
 }
 
 #[aoc(day8, part2)]
 fn highest_scenic_score(input: &ForestMap) -> usize {
-    let mut trees = input.trees.clone();
-
-    trees.sort_by(|a, b| a.get_scenic_score().partial_cmp(&b.get_scenic_score()).unwrap());
-
-    trees.last().unwrap().get_scenic_score()
+    input.best_scenic_tree().map_or(0, |(_, score)| score)
 }
 
 fn _highest_scenic_score(p0: &ForestMap) -> usize {
-    let mut trees = p0.trees.clone();
+    let mut trees = p0.trees.cells().to_vec();
 
     trees.sort_by(|a, b| a.get_scenic_score().partial_cmp(&b.get_scenic_score()).unwrap());
 
@@ -205,7 +340,7 @@ fn _highest_scenic_score(p0: &ForestMap) -> usize {
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
-    use crate::day8::{_highest_scenic_score, count_visible, ForestMap};
+    use crate::day8::{_highest_scenic_score, count_visible, highest_scenic_score, Direction, ForestMap, Shading};
 
     const EXAMPLE: &str = "30373
 25512
@@ -232,4 +367,154 @@ mod test {
         dbg!(&map);
         assert_eq!(8, _highest_scenic_score(&map))
     }
+
+    #[test]
+    fn test_direction_breakdown() {
+        let mut map = ForestMap::from_str(EXAMPLE).expect("Parse error");
+        map.set_scenic_scores();
+        let tree = map.tree(2, 3).unwrap();
+
+        assert_eq!(Some(((2, 3), 8)), map.best_scenic_tree());
+        assert_eq!(2, tree.viewing_distance(Direction::Up));
+        assert_eq!(2, tree.viewing_distance(Direction::Left));
+        assert_eq!(1, tree.viewing_distance(Direction::Down));
+        assert_eq!(2, tree.viewing_distance(Direction::Right));
+        assert!(tree.is_visible_from(Direction::Left));
+        assert!(!tree.is_visible_from(Direction::Up));
+
+        let middle = map.tree(2, 2).unwrap();
+        assert!(!middle.is_visible());
+        assert!(Direction::ALL.iter().all(|&direction| !middle.is_visible_from(direction)));
+    }
+
+    #[test]
+    fn test_heatmap_export() {
+        let mut map = ForestMap::from_str("09\n90").expect("Parse error");
+        map.set_scenic_scores();
+
+        assert_eq!("P2\n2 2\n255\n0 255\n255 0\n", map.to_pgm(Shading::Height));
+        assert_eq!(
+            "P3\n2 2\n255\n34 139 34  34 139 34\n34 139 34  34 139 34\n",
+            map.to_ppm(Shading::Visible)
+        );
+        let svg = map.to_svg(Shading::Scenic, 5);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"10\" height=\"10\">"));
+        assert_eq!(4, svg.matches("<rect").count());
+        assert!(map.write_image(std::path::Path::new("forest.png"), Shading::Height).is_err());
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        let map = ForestMap::from_str("1234\n1357\n1369").expect("Parse error");
+
+        assert_eq!(vec![(1, 0)], map.visible_along((0, 0), 0, (1, 0)));
+        assert_eq!(vec![(1, 1)], map.visible_along((0, 1), 1, (1, 0)));
+        assert_eq!(vec![(1, 2), (2, 2), (3, 2)], map.visible_along((0, 2), 1, (1, 0)));
+        assert_eq!(vec![(1, 1)], map.visible_along((0, 0), 0, (1, 1)));
+        assert_eq!(vec![(1, 1), (2, 2)], map.visible_along((0, 0), 4, (1, 1)));
+        // the sight line clips (1, 0) and (1, 1) on its way to (2, 1), and (1, 0) hides the rest
+        assert_eq!(vec![(1, 0), (3, 2)], map.visible_along((0, 0), 0, (2, 1)));
+        assert_eq!(vec![(1, 0), (1, 1), (2, 1), (3, 1), (3, 2)], map.visible_along((0, 0), 9, (2, 1)));
+        assert!(map.visible_along((0, 0), 0, (0, 0)).is_empty());
+        assert_eq!(
+            vec![(1, 0), (0, 1), (1, 1)],
+            map.visible_from((0, 0), 0)
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "Row 2 has 4 cells, expected 5",
+            ForestMap::from_str("30373\n25512\n6533").unwrap_err()
+        );
+        assert_eq!(
+            "(3, 1): 'x' is not a digit",
+            ForestMap::from_str("30373\n255x2").unwrap_err()
+        );
+        assert_eq!(
+            "(1, 0): '-2' is not a height",
+            ForestMap::from_str("1 -2\n3 4").unwrap_err()
+        );
+        assert_eq!(
+            "Row 1 has 1 cells, expected 2",
+            ForestMap::from_str("1,2\n3").unwrap_err()
+        );
+        assert_eq!(
+            "Row 1 is separated numbers but row 0 is digits",
+            ForestMap::from_str("30373\n2 5 5 1 2\n65332").unwrap_err()
+        );
+        assert_eq!(
+            "Row 0 is separated numbers but row 1 is digits",
+            ForestMap::from_str("30,373\n25512").unwrap_err()
+        );
+
+        // trailing whitespace doesn't turn a digit map into one column of big trees
+        let map = ForestMap::from_str("30373 \n25512\t\n65332").expect("Parse error");
+        assert_eq!(Some(0), map.tree(1, 0).map(|tree| tree.height()));
+        assert_eq!(3, map.tree(0, 0).unwrap().height());
+    }
+
+    #[test]
+    fn test_parse_separated_heights() {
+        let mut map = ForestMap::from_str("10 12 10\n12 11 12\n10, 12, 10").expect("Parse error");
+        map.set_scenic_scores();
+
+        assert_eq!(12, map.tree(1, 0).unwrap().height());
+        assert_eq!(8, count_visible(&map));
+        assert!(!map.tree(1, 1).unwrap().is_visible());
+    }
+
+    // Straightforward walk outwards from every tree, used to cross-check the stack sweeps.
+    fn naive_scores(input: &str) -> (usize, usize) {
+        let rows: Vec<Vec<u8>> = input.lines().map(|line| line.bytes().collect()).collect();
+        let (num_rows, num_cols) = (rows.len() as isize, rows[0].len() as isize);
+        let mut visible = 0;
+        let mut best = 0;
+        for row in 0..num_rows {
+            for col in 0..num_cols {
+                let height = rows[row as usize][col as usize];
+                let mut any_clear = false;
+                let mut score = 1;
+                for (dr, dc) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                    let (mut r, mut c, mut distance, mut clear) = (row + dr, col + dc, 0, true);
+                    while (0..num_rows).contains(&r) && (0..num_cols).contains(&c) {
+                        distance += 1;
+                        if rows[r as usize][c as usize] >= height {
+                            clear = false;
+                            break;
+                        }
+                        r += dr;
+                        c += dc;
+                    }
+                    any_clear |= clear;
+                    score *= distance;
+                }
+                visible += usize::from(any_clear);
+                best = best.max(score);
+            }
+        }
+        (visible, best)
+    }
+
+    #[test]
+    fn test_matches_naive() {
+        let mut seed: u64 = 0x2545F4914F6CDD1D;
+        let mut forest = String::new();
+        for _ in 0..37 {
+            for _ in 0..23 {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                forest.push(char::from(b'0' + (seed % 10) as u8));
+            }
+            forest.push('\n');
+        }
+
+        let mut map = ForestMap::from_str(&forest).expect("Parse error");
+        map.set_scenic_scores();
+
+        assert_eq!(naive_scores(&forest), (count_visible(&map), highest_scenic_score(&map)));
+        assert_eq!(naive_scores(EXAMPLE), (21, 8));
+    }
 }
@@ -1,123 +1,414 @@
 use std::collections::{HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
 
 use regex::Regex;
 
+/// A lattice point the rope can live on. Implemented for 2D `(x, y)` and 3D `(x, y, z)`.
+pub trait Position: Copy + Debug + Default + Eq + Hash {
+    fn add(self, other: Self) -> Self;
+    fn sub(self, other: Self) -> Self;
+    fn signum(self) -> Self;
+    fn chebyshev(self) -> usize;
+    fn manhattan(self) -> usize;
+    /// Unit step along the axis with the largest component, the first such axis on ties.
+    fn major_axis_step(self) -> Self;
+}
+
+impl Position for (isize, isize) {
+    fn add(self, other: Self) -> Self {
+        (self.0 + other.0, self.1 + other.1)
+    }
+
+    fn sub(self, other: Self) -> Self {
+        (self.0 - other.0, self.1 - other.1)
+    }
+
+    fn signum(self) -> Self {
+        (self.0.signum(), self.1.signum())
+    }
+
+    fn chebyshev(self) -> usize {
+        self.0.unsigned_abs().max(self.1.unsigned_abs())
+    }
+
+    fn manhattan(self) -> usize {
+        self.0.unsigned_abs() + self.1.unsigned_abs()
+    }
+
+    fn major_axis_step(self) -> Self {
+        if self.0.abs() >= self.1.abs() {
+            (self.0.signum(), 0)
+        } else {
+            (0, self.1.signum())
+        }
+    }
+}
+
+impl Position for (isize, isize, isize) {
+    fn add(self, other: Self) -> Self {
+        (self.0 + other.0, self.1 + other.1, self.2 + other.2)
+    }
+
+    fn sub(self, other: Self) -> Self {
+        (self.0 - other.0, self.1 - other.1, self.2 - other.2)
+    }
+
+    fn signum(self) -> Self {
+        (self.0.signum(), self.1.signum(), self.2.signum())
+    }
+
+    fn chebyshev(self) -> usize {
+        self.0.unsigned_abs().max(self.1.unsigned_abs()).max(self.2.unsigned_abs())
+    }
+
+    fn manhattan(self) -> usize {
+        self.0.unsigned_abs() + self.1.unsigned_abs() + self.2.unsigned_abs()
+    }
+
+    fn major_axis_step(self) -> Self {
+        let (x, y, z) = (self.0.abs(), self.1.abs(), self.2.abs());
+        if x >= y && x >= z {
+            (self.0.signum(), 0, 0)
+        } else if y >= z {
+            (0, self.1.signum(), 0)
+        } else {
+            (0, 0, self.2.signum())
+        }
+    }
+}
+
+/// Something that drives the head one unit step at a time.
+pub trait HeadMove {
+    type Pos: Position;
+
+    fn remaining(&self) -> usize;
+    fn next_step(&mut self) -> Self::Pos;
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Movement {
     UP(usize),
     DOWN(usize),
     LEFT(usize),
     RIGHT(usize),
+    UPLEFT(usize),
+    UPRIGHT(usize),
+    DOWNLEFT(usize),
+    DOWNRIGHT(usize),
+    /// Moves the head by `(dx, dy)` in total, one king's move at a time.
+    VECTOR(isize, isize),
 }
 
 impl Movement {
     fn get_amount(&self) -> usize {
         match self {
-            Movement::UP(amount) => *amount,
-            Movement::DOWN(amount) => *amount,
-            Movement::LEFT(amount) => *amount,
-            Movement::RIGHT(amount) => *amount
+            Movement::UP(amount)
+            | Movement::DOWN(amount)
+            | Movement::LEFT(amount)
+            | Movement::RIGHT(amount)
+            | Movement::UPLEFT(amount)
+            | Movement::UPRIGHT(amount)
+            | Movement::DOWNLEFT(amount)
+            | Movement::DOWNRIGHT(amount) => *amount,
+            Movement::VECTOR(dx, dy) => dx.unsigned_abs().max(dy.unsigned_abs()),
+        }
+    }
+
+    fn get_step(&self) -> (isize, isize) {
+        match self {
+            Movement::UP(_) => (0, 1),
+            Movement::DOWN(_) => (0, -1),
+            Movement::LEFT(_) => (-1, 0),
+            Movement::RIGHT(_) => (1, 0),
+            Movement::UPLEFT(_) => (-1, 1),
+            Movement::UPRIGHT(_) => (1, 1),
+            Movement::DOWNLEFT(_) => (-1, -1),
+            Movement::DOWNRIGHT(_) => (1, -1),
+            Movement::VECTOR(dx, dy) => (dx.signum(), dy.signum()),
+        }
+    }
+
+    fn take_step(&mut self) -> (isize, isize) {
+        let step = self.get_step();
+        match self {
+            Movement::UP(amount)
+            | Movement::DOWN(amount)
+            | Movement::LEFT(amount)
+            | Movement::RIGHT(amount)
+            | Movement::UPLEFT(amount)
+            | Movement::UPRIGHT(amount)
+            | Movement::DOWNLEFT(amount)
+            | Movement::DOWNRIGHT(amount) => *amount -= 1,
+            Movement::VECTOR(dx, dy) => {
+                *dx -= step.0;
+                *dy -= step.1;
+            }
+        }
+        step
+    }
+}
+
+impl HeadMove for Movement {
+    type Pos = (isize, isize);
+
+    fn remaining(&self) -> usize {
+        self.get_amount()
+    }
+
+    fn next_step(&mut self) -> Self::Pos {
+        self.take_step()
+    }
+}
+
+/// Head moves for a rope in space: `UP`/`DOWN` along y, `LEFT`/`RIGHT` along x and
+/// `FORWARD`/`BACK` along z.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Movement3D {
+    UP(usize),
+    DOWN(usize),
+    LEFT(usize),
+    RIGHT(usize),
+    FORWARD(usize),
+    BACK(usize),
+    /// Moves the head by `(dx, dy, dz)` in total, one king's move at a time.
+    VECTOR(isize, isize, isize),
+}
+
+impl HeadMove for Movement3D {
+    type Pos = (isize, isize, isize);
+
+    fn remaining(&self) -> usize {
+        match self {
+            Movement3D::UP(amount)
+            | Movement3D::DOWN(amount)
+            | Movement3D::LEFT(amount)
+            | Movement3D::RIGHT(amount)
+            | Movement3D::FORWARD(amount)
+            | Movement3D::BACK(amount) => *amount,
+            Movement3D::VECTOR(dx, dy, dz) => (*dx, *dy, *dz).chebyshev(),
+        }
+    }
+
+    fn next_step(&mut self) -> Self::Pos {
+        let step = match self {
+            Movement3D::UP(_) => (0, 1, 0),
+            Movement3D::DOWN(_) => (0, -1, 0),
+            Movement3D::LEFT(_) => (-1, 0, 0),
+            Movement3D::RIGHT(_) => (1, 0, 0),
+            Movement3D::FORWARD(_) => (0, 0, 1),
+            Movement3D::BACK(_) => (0, 0, -1),
+            Movement3D::VECTOR(dx, dy, dz) => (*dx, *dy, *dz).signum(),
+        };
+        match self {
+            Movement3D::UP(amount)
+            | Movement3D::DOWN(amount)
+            | Movement3D::LEFT(amount)
+            | Movement3D::RIGHT(amount)
+            | Movement3D::FORWARD(amount)
+            | Movement3D::BACK(amount) => *amount -= 1,
+            Movement3D::VECTOR(dx, dy, dz) => {
+                *dx -= step.0;
+                *dy -= step.1;
+                *dz -= step.2;
+            }
+        }
+        step
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Metric {
+    Chebyshev,
+    Manhattan,
+}
+
+impl Metric {
+    fn distance<P: Position>(&self, delta: P) -> usize {
+        match self {
+            Metric::Chebyshev => delta.chebyshev(),
+            Metric::Manhattan => delta.manhattan(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FollowPolicy {
+    /// King's moves, so the knot can cut corners diagonally.
+    Diagonal,
+    /// Rook's moves along whichever axis has the larger gap.
+    Orthogonal,
+    /// Never moves, e.g. a knot pinned to a post.
+    Anchored,
+}
+
+/// How a follower reacts to its parent: once the parent is more than `slack` away under
+/// `metric`, the knot steps towards it following `policy` until it is back within reach.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Physics {
+    pub slack: usize,
+    pub metric: Metric,
+    pub policy: FollowPolicy,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Physics {
+            slack: 1,
+            metric: Metric::Chebyshev,
+            policy: FollowPolicy::Diagonal,
         }
     }
 }
 
 #[derive(Clone)]
-struct Knot {
-    pos: (isize, isize),
-    visited: HashSet<(isize, isize)>,
+pub struct Knot<P: Position = (isize, isize)> {
+    pos: P,
+    // only tracked knots pay for a visit set
+    visited: Option<HashSet<P>>,
+    physics: Physics,
+    // false until the knot has followed its parent under its current physics
+    settled: bool,
 }
 
-impl Knot {
-    fn new() -> Knot {
+impl<P: Position> Knot<P> {
+    fn new() -> Knot<P> {
         Knot {
-            pos: (0, 0),
-            visited: HashSet::new(),
+            pos: P::default(),
+            visited: None,
+            physics: Physics::default(),
+            settled: true,
         }
     }
 
-    fn move_as_head(&mut self, movement: &mut Movement) {
-        match movement {
-            Movement::UP(amount) if *amount > 0 => {
-                self.pos.1 += 1;
-                *amount -= 1;
-            }
-            Movement::DOWN(amount) if *amount > 0 => {
-                self.pos.1 -= 1;
-                *amount -= 1;
-            }
-            Movement::LEFT(amount) if *amount > 0 => {
-                self.pos.0 -= 1;
-                *amount -= 1;
-            }
-            Movement::RIGHT(amount) if *amount > 0 => {
-                self.pos.0 += 1;
-                *amount -= 1;
-            }
-            _ => {}
+    fn track(&mut self) {
+        let pos = self.pos;
+        self.visited.get_or_insert_with(|| HashSet::from([pos]));
+    }
+
+    fn record_visit(&mut self) {
+        if let Some(visited) = &mut self.visited {
+            visited.insert(self.pos);
         }
     }
 
-    fn move_as_follower(&mut self, parent: &Knot) {
-        let diff_pos =
-            (parent.get_position().0 - self.pos.0, parent.get_position().1 - self.pos.1);
-        match diff_pos {
-            (x, y) if x.abs() > 1 && y == 0 => {
-                self.pos.0 += x / 2;
-            }
-            (x, y) if y.abs() > 1 && x == 0 => {
-                self.pos.1 += y / 2;
-            },
-            (x, y) if x.abs() > 1 && y.abs() == 1 => {
-                self.pos.0 += x / 2;
-                self.pos.1 += y;
-            },
-            (x, y) if x.abs() == 1 && y.abs() > 1 => {
-                self.pos.0 += x;
-                self.pos.1 += y / 2;
-            }
-            (x, y) if x.abs() > 1 && y.abs() > 1 => {
-                self.pos.0 += x / 2;
-                self.pos.1 += y / 2;
-            }
-            (_, _) => {}
+    fn move_as_head<M: HeadMove<Pos = P>>(&mut self, movement: &mut M) {
+        if movement.remaining() == 0 {
+            return;
         }
+        self.move_by(movement.next_step());
+    }
+
+    fn move_by(&mut self, step: P) {
+        self.pos = self.pos.add(step);
+        self.record_visit();
+    }
+
+    // Every step strictly shrinks the Manhattan distance, so the loop ends whichever metric decides
+    // when to stop; a rook's move can leave the Chebyshev distance as it was. With the default
+    // physics a single king's move is always enough, matching the puzzle rules.
+    fn move_as_follower(&mut self, parent: &P) -> bool {
+        self.settled = true;
+        let start = self.pos;
+        let mut diff = parent.sub(self.pos);
+        while self.physics.metric.distance(diff) > self.physics.slack {
+            let step = match self.physics.policy {
+                FollowPolicy::Diagonal => diff.signum(),
+                FollowPolicy::Orthogonal => diff.major_axis_step(),
+                FollowPolicy::Anchored => break,
+            };
+            self.pos = self.pos.add(step);
+            diff = diff.sub(step);
+        }
+
+        if self.pos == start {
+            return false;
+        }
+        self.record_visit();
+        true
+    }
 
-        self.visited.insert(self.pos);
+    pub fn physics(&self) -> &Physics {
+        &self.physics
     }
 
-    fn get_position(&self) -> &(isize, isize) {
+    /// Changes how this knot follows its parent, so one rope can mix e.g. a stiff segment and a
+    /// pinned knot. The knot catches up with the new physics on the next step.
+    pub fn set_physics(&mut self, physics: Physics) {
+        self.physics = physics;
+        self.settled = false;
+    }
+
+    pub fn get_position(&self) -> &P {
         &self.pos
     }
+
+    pub fn get_visited(&self) -> Option<&HashSet<P>> {
+        self.visited.as_ref()
+    }
+
+    pub fn visit_count(&self) -> usize {
+        self.visited.as_ref().map_or(0, |visited| visited.len())
+    }
 }
 
 #[aoc_generator(day9)]
 pub(crate) fn to_movements(input: &str) -> Vec<Movement> {
     let mut movements = Vec::new();
-    let matcher = Regex::new("^(?<dir>[DLRU]) (?<num>\\d+)$")
+    let matcher = Regex::new("^(?<dir>UL|UR|DL|DR|[DLRU]) (?<num>\\d+)$")
+        .expect("Could not build regex.");
+    let vector_matcher = Regex::new("^V (?<dx>-?\\d+) (?<dy>-?\\d+)$")
+        .expect("Could not build regex.");
+    for line in input.lines() {
+        if let Some(cap) = vector_matcher.captures(line) {
+            movements.push(Movement::VECTOR(
+                cap["dx"].parse().expect("Could not parse"),
+                cap["dy"].parse().expect("Could not parse"),
+            ));
+            continue;
+        }
+        if let Some(cap) = matcher.captures(line) {
+            let amount: usize = cap["num"].parse().expect("Could not parse");
+            match &cap["dir"] {
+                "U" => movements.push(Movement::UP(amount)),
+                "D" => movements.push(Movement::DOWN(amount)),
+                "L" => movements.push(Movement::LEFT(amount)),
+                "R" => movements.push(Movement::RIGHT(amount)),
+                "UL" => movements.push(Movement::UPLEFT(amount)),
+                "UR" => movements.push(Movement::UPRIGHT(amount)),
+                "DL" => movements.push(Movement::DOWNLEFT(amount)),
+                "DR" => movements.push(Movement::DOWNRIGHT(amount)),
+                _ => panic!("Unknown char in input."),
+            }
+        }
+    }
+    movements
+}
+
+pub fn to_movements_3d(input: &str) -> Vec<Movement3D> {
+    let mut movements = Vec::new();
+    let matcher = Regex::new("^(?<dir>[DLRUFB]) (?<num>\\d+)$")
+        .expect("Could not build regex.");
+    let vector_matcher = Regex::new("^V (?<dx>-?\\d+) (?<dy>-?\\d+) (?<dz>-?\\d+)$")
         .expect("Could not build regex.");
     for line in input.lines() {
+        if let Some(cap) = vector_matcher.captures(line) {
+            movements.push(Movement3D::VECTOR(
+                cap["dx"].parse().expect("Could not parse"),
+                cap["dy"].parse().expect("Could not parse"),
+                cap["dz"].parse().expect("Could not parse"),
+            ));
+            continue;
+        }
         if let Some(cap) = matcher.captures(line) {
+            let amount: usize = cap["num"].parse().expect("Could not parse");
             match &cap["dir"] {
-                "U" => movements.push(Movement::UP(cap.name("num")
-                    .expect("No match")
-                    .as_str()
-                    .parse()
-                    .expect("Could not parse"))),
-                "D" => movements.push(Movement::DOWN(cap.name("num")
-                    .expect("No match")
-                    .as_str()
-                    .parse()
-                    .expect("Could not parse"))),
-                "L" => movements.push(Movement::LEFT(cap.name("num")
-                    .expect("No match")
-                    .as_str()
-                    .parse()
-                    .expect("Could not parse"))),
-                "R" => movements.push(Movement::RIGHT(cap.name("num")
-                    .expect("No match")
-                    .as_str()
-                    .parse()
-                    .expect("Could not parse"))),
+                "U" => movements.push(Movement3D::UP(amount)),
+                "D" => movements.push(Movement3D::DOWN(amount)),
+                "L" => movements.push(Movement3D::LEFT(amount)),
+                "R" => movements.push(Movement3D::RIGHT(amount)),
+                "F" => movements.push(Movement3D::FORWARD(amount)),
+                "B" => movements.push(Movement3D::BACK(amount)),
                 _ => panic!("Unknown char in input."),
             }
         }
@@ -133,38 +424,223 @@ fn do_movements(input: &[Movement]) -> usize {
     iter_moves(&mut movements, &mut knots).len()
 }
 
-fn iter_moves<'a>(movements: &'a mut [Movement], knots: &'a mut Vec<Knot>) -> &'a HashSet<(isize, isize)> {
+pub fn iter_moves<'a, M: HeadMove>(movements: &'a mut [M], knots: &'a mut [Knot<M::Pos>]) -> &'a HashSet<M::Pos> {
     movements.iter_mut().for_each(|movement| {
-        while movement.get_amount() > 0 {
+        while movement.remaining() > 0 {
             do_move(knots, movement);
         }
     });
 
-    &knots.last().unwrap().visited
+    knots.last().unwrap().visited.as_ref().expect("The tail is not tracked")
+}
+
+pub fn build_rope(num_knots: usize) -> Vec<Knot> {
+    build_rope_with(num_knots, Physics::default())
 }
 
-fn build_rope(num_knots: usize) -> Vec<Knot> {
+pub fn build_rope_with<P: Position>(num_knots: usize, physics: Physics) -> Vec<Knot<P>> {
     let mut knots = Vec::new();
     (0..num_knots).for_each(|_| {
-        knots.push(Knot::new());
+        let mut knot = Knot::new();
+        knot.set_physics(physics);
+        knots.push(knot);
     });
+    if let Some(tail) = knots.last_mut() {
+        tail.track();
+    }
 
     knots
 }
 
-fn do_move(knots: &mut Vec<Knot>, movement: &mut Movement) {
+pub fn track_all<P: Position>(knots: &mut [Knot<P>]) {
+    knots.iter_mut().for_each(|knot| knot.track());
+}
+
+fn knot_label(idx: usize, num_knots: usize) -> Option<char> {
+    match idx {
+        0 => Some('H'),
+        _ if idx == num_knots - 1 && (num_knots == 2 || idx > 9) => Some('T'),
+        1..=9 => char::from_digit(idx as u32, 10),
+        _ => None,
+    }
+}
+
+/// Draws the rope like the puzzle diagrams, top row first: `H` for the head, `1`-`9` for the
+/// knots behind it and `T` for a tail without a digit, `s` for the start, and `#` for cells the
+/// tail has visited when `show_visits` is set. Knots nearer the head are drawn on top.
+pub fn render_rope(knots: &[Knot], show_visits: bool) -> String {
+    let mut cells: Vec<(isize, isize)> = knots.iter().map(|knot| knot.pos).collect();
+    cells.push((0, 0));
+    if show_visits {
+        if let Some(visited) = knots.last().and_then(|tail| tail.get_visited()) {
+            cells.extend(visited);
+        }
+    }
+    let min = (cells.iter().map(|pos| pos.0).min().unwrap(), cells.iter().map(|pos| pos.1).min().unwrap());
+    let max = (cells.iter().map(|pos| pos.0).max().unwrap(), cells.iter().map(|pos| pos.1).max().unwrap());
+
+    render_rope_in(knots, show_visits, min, max)
+}
+
+pub fn render_rope_in(knots: &[Knot], show_visits: bool, min: (isize, isize), max: (isize, isize)) -> String {
+    let width = (max.0 - min.0 + 1) as usize;
+    let height = (max.1 - min.1 + 1) as usize;
+    let mut rows = vec![vec!['.'; width]; height];
+    let mut put = |pos: (isize, isize), c: char| {
+        if (min.0..=max.0).contains(&pos.0) && (min.1..=max.1).contains(&pos.1) {
+            rows[(max.1 - pos.1) as usize][(pos.0 - min.0) as usize] = c;
+        }
+    };
+
+    if show_visits {
+        if let Some(visited) = knots.last().and_then(|tail| tail.get_visited()) {
+            visited.iter().for_each(|&pos| put(pos, '#'));
+        }
+    }
+    put((0, 0), 's');
+    for (idx, knot) in knots.iter().enumerate().rev() {
+        if let Some(label) = knot_label(idx, knots.len()) {
+            put(knot.pos, label);
+        }
+    }
+
+    let mut out = String::new();
+    for row in rows {
+        out.extend(row);
+        out.push('\n');
+    }
+    out
+}
+
+fn do_move<M: HeadMove>(knots: &mut [Knot<M::Pos>], movement: &mut M) {
     knots.first_mut().unwrap().move_as_head(movement);
-    for i in 0..knots.len() - 1 {
-        move_follower(knots, i);
+    pull_followers(knots, |_, _| {});
+}
+
+// Knots are updated in place, and a knot that stays put means nothing behind it moves either,
+// unless one of them has had its physics changed since it last followed.
+// `on_move` gets the index and previous position of every follower that moved.
+fn pull_followers<P: Position, F: FnMut(usize, P)>(knots: &mut [Knot<P>], mut on_move: F) {
+    for i in 1..knots.len() {
+        let parent = *knots[i - 1].get_position();
+        let previous = *knots[i].get_position();
+        if knots[i].move_as_follower(&parent) {
+            on_move(i, previous);
+        } else if knots[i + 1..].iter().all(|knot| knot.settled) {
+            break;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot<P: Position = (isize, isize)> {
+    /// Number of head steps taken so far.
+    pub step: usize,
+    /// Every knot's position, head first.
+    pub knots: Vec<P>,
+    /// Indices of the knots that moved to reach this state.
+    pub moved: Vec<usize>,
+}
+
+impl<P: Position> Snapshot<P> {
+    pub fn head(&self) -> P {
+        self.knots[0]
     }
 }
 
-fn move_follower(knots: &mut [Knot], parent_idx: usize) {
-    let binding = knots.to_owned();
-    let parent = binding.get(parent_idx).unwrap();
-    knots.get_mut(parent_idx + 1)
-        .unwrap()
-        .move_as_follower(parent);
+/// Replays movements one head step at a time, keeping an undo log so it can step backwards and
+/// seek. Stepping back cannot un-record visits, so the simulation's knots do not track any.
+pub struct RopeSimulation<P: Position = (isize, isize)> {
+    head_steps: Vec<P>,
+    knots: Vec<Knot<P>>,
+    undo: Vec<Vec<(usize, P)>>,
+}
+
+impl<P: Position> RopeSimulation<P> {
+    pub fn new<M: HeadMove<Pos = P> + Clone>(movements: &[M], mut knots: Vec<Knot<P>>) -> Self {
+        let mut head_steps = Vec::new();
+        for movement in movements {
+            let mut movement = movement.clone();
+            while movement.remaining() > 0 {
+                head_steps.push(movement.next_step());
+            }
+        }
+        knots.iter_mut().for_each(|knot| knot.visited = None);
+
+        RopeSimulation {
+            head_steps,
+            knots,
+            undo: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.head_steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head_steps.is_empty()
+    }
+
+    pub fn current_step(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn knots(&self) -> &[Knot<P>] {
+        &self.knots
+    }
+
+    pub fn snapshot(&self) -> Snapshot<P> {
+        self.snapshot_with(Vec::new())
+    }
+
+    fn snapshot_with(&self, moved: Vec<usize>) -> Snapshot<P> {
+        Snapshot {
+            step: self.current_step(),
+            knots: self.knots.iter().map(|knot| knot.pos).collect(),
+            moved,
+        }
+    }
+
+    pub fn step_forward(&mut self) -> Option<Snapshot<P>> {
+        let &step = self.head_steps.get(self.current_step())?;
+        let mut changes = vec![(0, self.knots[0].pos)];
+        self.knots[0].move_by(step);
+        pull_followers(&mut self.knots, |idx, previous| changes.push((idx, previous)));
+
+        let moved = changes.iter().map(|&(idx, _)| idx).collect();
+        self.undo.push(changes);
+        Some(self.snapshot_with(moved))
+    }
+
+    pub fn step_back(&mut self) -> Option<Snapshot<P>> {
+        let changes = self.undo.pop()?;
+        for &(idx, previous) in &changes {
+            self.knots[idx].pos = previous;
+        }
+
+        Some(self.snapshot_with(changes.iter().map(|&(idx, _)| idx).collect()))
+    }
+
+    /// Moves forwards or backwards to `step`, stopping at the last step if it is out of range.
+    pub fn seek(&mut self, step: usize) -> Snapshot<P> {
+        let step = step.min(self.len());
+        while self.current_step() < step {
+            self.step_forward();
+        }
+        while self.current_step() > step {
+            self.step_back();
+        }
+        self.snapshot()
+    }
+}
+
+impl<P: Position> Iterator for RopeSimulation<P> {
+    type Item = Snapshot<P>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step_forward()
+    }
 }
 
 #[aoc(day9, part2)]
@@ -178,7 +654,7 @@ fn do_movements_long(input: &[Movement]) -> usize {
 #[cfg(test)]
 mod test {
     use std::collections::HashSet;
-    use crate::day9::{build_rope, do_movements, do_movements_long, iter_moves, Knot, Movement, to_movements};
+    use crate::day9::{Position, build_rope, build_rope_with, do_movements, do_movements_long, iter_moves, render_rope, render_rope_in, track_all, FollowPolicy, Knot, Metric, Movement, Movement3D, Physics, RopeSimulation, to_movements, to_movements_3d};
 
     const EXAMPLE: &str = "R 4
 U 4
@@ -279,6 +755,224 @@ U 20";
         assert_eq!(cmp_set, *tail_pos);
     }
 
+    #[test]
+    fn test_to_movement_diagonal_and_vector() {
+        let movement_list = to_movements("UL 2\nDR 3\nUR 1\nDL 4\nV 3 -2\nX 1");
+
+        assert_eq!(
+            vec![
+                Movement::UPLEFT(2),
+                Movement::DOWNRIGHT(3),
+                Movement::UPRIGHT(1),
+                Movement::DOWNLEFT(4),
+                Movement::VECTOR(3, -2),
+            ],
+            movement_list
+        );
+    }
+
+    #[test]
+    fn test_head_move_vector() {
+        let mut head = Knot::new();
+        let mut movement = Movement::VECTOR(3, -2);
+
+        while movement.get_amount() > 0 {
+            head.move_as_head(&mut movement);
+        }
+
+        assert_eq!(Movement::VECTOR(0, 0), movement);
+        assert_eq!((3, -2), head.pos);
+    }
+
+    #[test]
+    fn test_diagonal_rope() {
+        let mut movement_list = to_movements("UR 4\nDL 1\nV -3 0");
+        let mut knots = build_rope(3);
+        iter_moves(&mut movement_list, &mut knots);
+
+        let positions: Vec<(isize, isize)> = knots.iter().map(|knot| knot.pos).collect();
+        assert_eq!(vec![(0, 3), (1, 3), (2, 2)], positions);
+        assert!(knots.windows(2).all(|pair| {
+            (pair[0].pos.0 - pair[1].pos.0).abs() <= 1 && (pair[0].pos.1 - pair[1].pos.1).abs() <= 1
+        }));
+    }
+
+    #[test]
+    fn test_elastic_rope() {
+        let physics = Physics { slack: 3, ..Physics::default() };
+        let mut movement_list = to_movements("R 5\nU 2");
+        let mut knots = build_rope_with(2, physics);
+        iter_moves(&mut movement_list, &mut knots);
+
+        assert_eq!((2, 0), knots[1].pos);
+    }
+
+    #[test]
+    fn test_manhattan_orthogonal_rope() {
+        let physics = Physics {
+            slack: 1,
+            metric: Metric::Manhattan,
+            policy: FollowPolicy::Orthogonal,
+        };
+        let mut movement_list = to_movements("UR 1");
+        let mut knots = build_rope_with(2, physics);
+        iter_moves(&mut movement_list, &mut knots);
+
+        assert_eq!((1, 0), knots[1].pos);
+
+        let mut movement_list = to_movements("UR 2");
+        let mut knots = build_rope_with(2, physics);
+        iter_moves(&mut movement_list, &mut knots);
+
+        assert_eq!((2, 1), knots[1].pos);
+    }
+
+    #[test]
+    fn test_anchored_knot() {
+        let mut movement_list = to_movements(EXAMPLE_P2);
+        let mut knots = build_rope(3);
+        let pinned = Physics { policy: FollowPolicy::Anchored, ..*knots[2].physics() };
+        knots[2].set_physics(pinned);
+        let tail_pos = iter_moves(&mut movement_list, &mut knots);
+
+        assert_eq!(1, tail_pos.len());
+        assert_eq!((0, 0), knots[2].pos);
+    }
+
+    #[test]
+    fn test_physics_changed_mid_run() {
+        let mut knots = build_rope(3);
+        iter_moves(&mut to_movements("R 4"), &mut knots);
+        assert_eq!((2, 0), knots[2].pos);
+
+        // the middle knot doesn't move on the next step, but the tail must still close up
+        let taut = Physics { slack: 0, ..*knots[2].physics() };
+        knots[2].set_physics(taut);
+        iter_moves(&mut to_movements("U 1"), &mut knots);
+        assert_eq!((3, 0), knots[1].pos);
+        assert_eq!((3, 0), knots[2].pos);
+    }
+
+    #[test]
+    fn test_long_rope() {
+        let mut movement_list = to_movements("R 3000\nU 3000\nL 6000");
+        let mut knots = build_rope(2000);
+        let visited = iter_moves(&mut movement_list, &mut knots).len();
+
+        assert_eq!(6004, visited);
+        assert_eq!((-1001, 3000), knots[1999].pos);
+        assert!(knots[..1999].iter().all(|knot| knot.visited.is_none()));
+    }
+
+    #[test]
+    fn test_track_all_knots() {
+        let mut movement_list = to_movements(EXAMPLE_P2);
+        let mut knots = build_rope(10);
+        track_all(&mut knots);
+        iter_moves(&mut movement_list, &mut knots);
+
+        assert_eq!(36, knots[9].visit_count());
+        assert!(knots.windows(2).all(|pair| pair[0].visit_count() >= pair[1].visit_count()));
+        assert!(knots.iter().all(|knot| knot.get_visited().unwrap().contains(&(0, 0))));
+    }
+
+    #[test]
+    fn test_render_rope() {
+        let mut movement_list = to_movements("R 4");
+        let mut knots = build_rope(2);
+        iter_moves(&mut movement_list, &mut knots);
+
+        assert_eq!("......\n......\n......\n......\ns..TH.\n", render_rope_in(&knots, false, (0, 0), (5, 4)));
+        assert_eq!("s##TH\n", render_rope(&knots, true));
+
+        let mut movement_list = to_movements(EXAMPLE);
+        let mut knots = build_rope(2);
+        iter_moves(&mut movement_list, &mut knots);
+        let visits = render_rope_in(&knots, true, (0, 0), (5, 4))
+            .replace(['H', 'T'], "#");
+
+        assert_eq!("..##..\n...##.\n.####.\n....#.\ns###..\n", visits);
+    }
+
+    #[test]
+    fn test_render_long_rope() {
+        let mut movement_list = to_movements("R 5\nU 8");
+        let mut knots = build_rope(10);
+        iter_moves(&mut movement_list, &mut knots);
+
+        assert_eq!(
+            "..........\n.....H....\n.....1....\n.....2....\n.....3....\n....54....\n...6......\n..7.......\n.8........\n9.........\n",
+            render_rope_in(&knots, false, (0, 0), (9, 9))
+        );
+    }
+
+    #[test]
+    fn test_simulation_steps() {
+        let movement_list = to_movements("R 2\nUL 1");
+        let mut sim = RopeSimulation::new(&movement_list, build_rope(3));
+
+        assert_eq!(3, sim.len());
+        let snapshots: Vec<_> = sim.by_ref().collect();
+        assert_eq!(vec![(1, 0), (0, 0), (0, 0)], snapshots[0].knots);
+        assert_eq!(vec![0], snapshots[0].moved);
+        assert_eq!(vec![(2, 0), (1, 0), (0, 0)], snapshots[1].knots);
+        assert_eq!(vec![0, 1], snapshots[1].moved);
+        assert_eq!((1, 1), snapshots[2].head());
+        assert_eq!(3, snapshots[2].step);
+        assert_eq!(None, sim.step_forward());
+    }
+
+    #[test]
+    fn test_simulation_replay() {
+        let movement_list = to_movements(EXAMPLE_P2);
+        let mut sim = RopeSimulation::new(&movement_list, build_rope(10));
+        let forward: Vec<_> = sim.by_ref().collect();
+
+        let mut knots = build_rope(10);
+        iter_moves(&mut movement_list.clone(), &mut knots);
+        assert_eq!(knots.iter().map(|knot| knot.pos).collect::<Vec<_>>(), forward.last().unwrap().knots);
+
+        let back = sim.step_back().unwrap();
+        assert_eq!(forward[forward.len() - 2].knots, back.knots);
+        assert_eq!(forward.last().unwrap().moved, back.moved);
+        assert_eq!(forward[10].knots, sim.seek(11).knots);
+        assert_eq!(forward[40].knots, sim.seek(41).knots);
+        assert_eq!(vec![(0, 0); 10], sim.seek(0).knots);
+        assert_eq!(None, sim.step_back());
+        assert_eq!(forward.len(), sim.seek(usize::MAX).step);
+    }
+
+    #[test]
+    fn test_to_movements_3d() {
+        assert_eq!(
+            vec![Movement3D::FORWARD(2), Movement3D::BACK(1), Movement3D::UP(3), Movement3D::VECTOR(1, -2, 3)],
+            to_movements_3d("F 2\nB 1\nU 3\nV 1 -2 3")
+        );
+    }
+
+    #[test]
+    fn test_rope_3d() {
+        let mut movement_list = to_movements_3d("F 4\nR 2\nV 0 3 -3");
+        let mut knots = build_rope_with(3, Physics::default());
+        iter_moves(&mut movement_list, &mut knots);
+
+        let positions: Vec<(isize, isize, isize)> = knots.iter().map(|knot| knot.pos).collect();
+        assert_eq!(vec![(2, 3, 1), (2, 2, 2), (2, 1, 2)], positions);
+        assert!(knots.windows(2).all(|pair| pair[0].pos.sub(pair[1].pos).chebyshev() <= 1));
+    }
+
+    #[test]
+    fn test_flat_3d_matches_2d() {
+        let mut movement_list = to_movements(EXAMPLE_P2);
+        let mut knots = build_rope(10);
+        let flat = iter_moves(&mut movement_list, &mut knots).len();
+
+        let mut movement_list = to_movements_3d(&EXAMPLE_P2.replace('U', "F").replace('D', "B"));
+        let mut knots = build_rope_with::<(isize, isize, isize)>(10, Physics::default());
+
+        assert_eq!(flat, iter_moves(&mut movement_list, &mut knots).len());
+    }
+
     #[test]
     fn test_sample() {
         let movement_list = to_movements(EXAMPLE);
@@ -0,0 +1,345 @@
+use std::fmt;
+use std::ops::{Index, IndexMut};
+
+/// Offsets to the four orthogonal neighbours, with `y` growing downwards.
+pub const DIRS4: [(isize, isize); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+/// Offsets to all eight neighbours, clockwise from north.
+pub const DIRS8: [(isize, isize); 8] = [
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+    (-1, -1),
+];
+
+/// A rectangular, row-major grid addressed by `(x, y)` with `(0, 0)` at the top left.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Grid<T> {
+    cells: Vec<T>,
+    width: usize,
+    height: usize,
+}
+
+impl<T: Clone> Grid<T> {
+    pub fn new(width: usize, height: usize, fill: T) -> Self {
+        Grid {
+            cells: vec![fill; width * height],
+            width,
+            height,
+        }
+    }
+}
+
+impl<T> Grid<T> {
+    pub fn from_vec(width: usize, height: usize, cells: Vec<T>) -> Result<Self, String> {
+        if cells.len() != width * height {
+            return Err(format!(
+                "Expected {} cells for a {}x{} grid, got {}",
+                width * height,
+                width,
+                height,
+                cells.len()
+            ));
+        }
+        Ok(Grid {
+            cells,
+            width,
+            height,
+        })
+    }
+
+    pub fn from_rows(rows: Vec<Vec<T>>) -> Result<Self, String> {
+        let width = rows.first().map_or(0, |row| row.len());
+        let height = rows.len();
+        let mut cells = Vec::with_capacity(width * height);
+        for (y, row) in rows.into_iter().enumerate() {
+            if row.len() != width {
+                return Err(format!(
+                    "Row {} has {} cells, expected {}",
+                    y,
+                    row.len(),
+                    width
+                ));
+            }
+            cells.extend(row);
+        }
+        Ok(Grid {
+            cells,
+            width,
+            height,
+        })
+    }
+
+    /// Builds a grid from a character map, one line per row, converting each cell with `f`.
+    /// Errors from `f` and ragged rows are reported with their position.
+    pub fn parse<F>(s: &str, mut f: F) -> Result<Self, String>
+    where
+        F: FnMut(char) -> Result<T, String>,
+    {
+        let mut rows = Vec::new();
+        for (y, line) in s.lines().enumerate() {
+            let row = line
+                .chars()
+                .enumerate()
+                .map(|(x, c)| f(c).map_err(|err| format!("({}, {}): {}", x, y, err)))
+                .collect::<Result<Vec<T>, String>>()?;
+            rows.push(row);
+        }
+        Self::from_rows(rows)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    pub fn index_of(&self, x: usize, y: usize) -> usize {
+        x + y * self.width
+    }
+
+    pub fn coords_of(&self, idx: usize) -> (usize, usize) {
+        (idx % self.width, idx / self.width)
+    }
+
+    pub fn contains(&self, x: isize, y: isize) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+    }
+
+    pub fn offset(&self, (x, y): (usize, usize), (dx, dy): (isize, isize)) -> Option<(usize, usize)> {
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        if self.contains(nx, ny) {
+            Some((nx as usize, ny as usize))
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<&T> {
+        if x < self.width && y < self.height {
+            self.cells.get(self.index_of(x, y))
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut T> {
+        if x < self.width && y < self.height {
+            let idx = self.index_of(x, y);
+            self.cells.get_mut(idx)
+        } else {
+            None
+        }
+    }
+
+    pub fn cells(&self) -> &[T] {
+        &self.cells
+    }
+
+    pub fn cells_mut(&mut self) -> &mut [T] {
+        &mut self.cells
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ((usize, usize), &T)> + '_ {
+        self.cells
+            .iter()
+            .enumerate()
+            .map(|(idx, cell)| (self.coords_of(idx), cell))
+    }
+
+    pub fn row(&self, y: usize) -> &[T] {
+        &self.cells[y * self.width..(y + 1) * self.width]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[T]> + '_ {
+        (0..self.height).map(|y| self.row(y))
+    }
+
+    pub fn column(&self, x: usize) -> impl Iterator<Item = &T> + '_ {
+        (0..self.height).map(move |y| &self[(x, y)])
+    }
+
+    /// Coordinates stepping from `start` by `step` until the edge, excluding `start` itself.
+    pub fn ray(
+        &self,
+        start: (usize, usize),
+        step: (isize, isize),
+    ) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let mut cur = Some(start);
+        std::iter::from_fn(move || {
+            if step == (0, 0) {
+                return None;
+            }
+            cur = self.offset(cur?, step);
+            cur
+        })
+    }
+
+    /// Every cell crossed by a line from the centre of `start` heading along `direction`, nearest
+    /// first, until the edge, excluding `start` itself. Unlike `ray`, cells the line only clips
+    /// between lattice steps are included; a line passing exactly through a corner goes straight
+    /// to the diagonal cell.
+    pub fn trace(
+        &self,
+        start: (usize, usize),
+        direction: (isize, isize),
+    ) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let (dx, dy) = direction;
+        let mut cur = Some(start);
+        // boundaries crossed so far along each axis; the line meets the next x boundary at
+        // (2 * crossed_x + 1) / (2 * |dx|), so scaling both by 2|dx||dy| keeps the comparison integral
+        let (mut crossed_x, mut crossed_y) = (0, 0);
+        std::iter::from_fn(move || {
+            let next_x = (dx != 0).then(|| (2 * crossed_x + 1) * dy.abs());
+            let next_y = (dy != 0).then(|| (2 * crossed_y + 1) * dx.abs());
+            let step = match (next_x, next_y) {
+                (None, None) => return None,
+                (Some(_), None) => (dx.signum(), 0),
+                (None, Some(_)) => (0, dy.signum()),
+                (Some(x), Some(y)) if x < y => (dx.signum(), 0),
+                (Some(x), Some(y)) if x > y => (0, dy.signum()),
+                (Some(_), Some(_)) => (dx.signum(), dy.signum()),
+            };
+            crossed_x += step.0.abs();
+            crossed_y += step.1.abs();
+            cur = self.offset(cur?, step);
+            cur
+        })
+    }
+
+    pub fn neighbors4(&self, pos: (usize, usize)) -> impl Iterator<Item = (usize, usize)> + '_ {
+        DIRS4.iter().filter_map(move |&step| self.offset(pos, step))
+    }
+
+    pub fn neighbors8(&self, pos: (usize, usize)) -> impl Iterator<Item = (usize, usize)> + '_ {
+        DIRS8.iter().filter_map(move |&step| self.offset(pos, step))
+    }
+
+    pub fn map<U, F: FnMut(&T) -> U>(&self, f: F) -> Grid<U> {
+        Grid {
+            cells: self.cells.iter().map(f).collect(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    /// Draws the grid one character per cell, with a newline after every row.
+    pub fn render<F: Fn(&T) -> char>(&self, f: F) -> String {
+        let mut out = String::with_capacity((self.width + 1) * self.height);
+        for row in self.rows() {
+            out.extend(row.iter().map(&f));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl Grid<char> {
+    pub fn parse_chars(s: &str) -> Result<Self, String> {
+        Self::parse(s, Ok)
+    }
+}
+
+impl<T> Index<(usize, usize)> for Grid<T> {
+    type Output = T;
+
+    fn index(&self, (x, y): (usize, usize)) -> &Self::Output {
+        assert!(x < self.width && y < self.height, "({}, {}) is outside the grid", x, y);
+        &self.cells[x + y * self.width]
+    }
+}
+
+impl<T> IndexMut<(usize, usize)> for Grid<T> {
+    fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut Self::Output {
+        assert!(x < self.width && y < self.height, "({}, {}) is outside the grid", x, y);
+        &mut self.cells[x + y * self.width]
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Grid<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in self.rows() {
+            for cell in row {
+                write!(f, "{}", cell)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grid::Grid;
+
+    const EXAMPLE: &str = "abc
+def";
+
+    #[test]
+    fn test_parse_and_access() {
+        let grid = Grid::parse_chars(EXAMPLE).unwrap();
+
+        assert_eq!((3, 2), (grid.width(), grid.height()));
+        assert_eq!(Some(&'f'), grid.get(2, 1));
+        assert_eq!(None, grid.get(3, 0));
+        assert_eq!('d', grid[(0, 1)]);
+        assert_eq!(vec!['b', 'e'], grid.column(1).copied().collect::<Vec<char>>());
+        assert_eq!(EXAMPLE.to_owned() + "\n", grid.to_string());
+    }
+
+    #[test]
+    fn test_ragged_and_bad_cells() {
+        assert_eq!(Err("Row 1 has 2 cells, expected 3".to_owned()), Grid::parse_chars("abc\nde"));
+        assert_eq!(
+            Err("(1, 1): not a digit".to_owned()),
+            Grid::parse("12\n3x", |c| c.to_digit(10).ok_or("not a digit".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_neighbors_and_rays() {
+        let grid = Grid::new(3, 3, 0);
+
+        assert_eq!(2, grid.neighbors4((0, 0)).count());
+        assert_eq!(8, grid.neighbors8((1, 1)).count());
+        assert_eq!(3, grid.neighbors8((2, 2)).count());
+        assert_eq!(vec![(1, 1), (0, 2)], grid.ray((2, 0), (-1, 1)).collect::<Vec<_>>());
+        assert_eq!(0, grid.ray((0, 0), (0, -1)).count());
+    }
+
+    #[test]
+    fn test_trace() {
+        let grid = Grid::new(5, 3, 0);
+
+        assert_eq!(
+            vec![(1, 0), (1, 1), (2, 1), (3, 1), (3, 2), (4, 2)],
+            grid.trace((0, 0), (2, 1)).collect::<Vec<_>>()
+        );
+        assert_eq!(vec![(1, 1), (2, 2)], grid.trace((0, 0), (1, 1)).collect::<Vec<_>>());
+        assert_eq!(vec![(2, 1), (2, 0)], grid.trace((2, 2), (0, -1)).collect::<Vec<_>>());
+        assert_eq!(vec![(4, 1), (3, 1), (3, 0)], grid.trace((4, 2), (-1, -2)).collect::<Vec<_>>());
+        assert_eq!(0, grid.trace((1, 1), (0, 0)).count());
+    }
+
+    #[test]
+    fn test_map_and_render() {
+        let grid = Grid::parse("10\n01", |c| Ok(c == '1')).unwrap();
+
+        assert_eq!("#.\n.#\n", grid.render(|&on| if on { '#' } else { '.' }));
+        assert_eq!(Grid::from_vec(2, 2, vec![1, 0, 0, 1]).unwrap(), grid.map(|&on| usize::from(on)));
+    }
+}
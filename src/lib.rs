@@ -14,5 +14,6 @@ pub mod day8;
 pub mod day9;
 pub mod day10;
 pub mod day11;
+pub mod grid;
 
 aoc_lib! { year = 2022 }